@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use argos_macros::{route, register};
+use serde_json;
+use serde::{Deserialize, Serialize};
+use argos::{server::Server, request::HttpRequest, error::ReturnError, multipart::MultipartLimits};
+
+#[derive(Deserialize)]
+pub struct Greeting {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    name: String,
+    greeting: String,
+}
+
+#[route(POST, path = "/api/hello", formatter = "json")]
+pub async fn hello(mut req: HttpRequest) -> Result<Hello, ReturnError<String>> {
+    let greeting = req.json::<Greeting>().await?;
+    Ok(Hello { name: greeting.name, greeting: "hello".to_string() })
+}
+
+#[route(POST | PUT, path = "/api/echo", formatter = "text")]
+pub async fn echo(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    req.text().await
+}
+
+/// Streams the upload to disk instead of buffering it, e.g. `curl -T big.iso`.
+#[route(PUT, path = "/api/upload", formatter = "text")]
+pub async fn upload(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let path = std::env::temp_dir().join("argos-upload");
+    let mut file = tokio::fs::File::create(&path).await
+        .map_err(|err| ReturnError::new(500, err.to_string()))?;
+    let written = req.pipe_to(&mut file).await?;
+    Ok(format!("{} bytes written to {}", written, path.display()))
+}
+
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Accepts a browser form, e.g. `curl -F name=argos -F file=@Cargo.toml`.
+/// Files are streamed to disk, each one limited to 16 MiB.
+#[route(POST, path = "/api/form", formatter = "text")]
+pub async fn form(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let limits = MultipartLimits::new().per_part(16 * 1024 * 1024).total(64 * 1024 * 1024);
+    let mut multipart = req.multipart_with_limits(limits)?;
+    let mut summary = String::new();
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        if field.file_name().is_some() {
+            // the field and file names come from the client, never use them in the path
+            let path = std::env::temp_dir().join(format!("argos-form-{}", UPLOADS.fetch_add(1, Ordering::Relaxed)));
+            let mut file = tokio::fs::File::create(&path).await
+                .map_err(|err| ReturnError::new(500, err.to_string()))?;
+            let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+            let written = field.pipe_to(&mut file).await?;
+            summary.push_str(&format!("{}: {} bytes of {} written to {}\n", name, written, content_type, path.display()));
+        } else {
+            summary.push_str(&format!("{} = {}\n", name, field.text().await?));
+        }
+    }
+    Ok(summary)
+}
+
+#[tokio::main]
+async fn main() {
+    let server = Server::builder(([127, 0, 0, 1], 3000).into())
+        .max_body_size(64 * 1024)
+        .build()
+        .await
+        .unwrap();
+    server.start().await.unwrap();
+}
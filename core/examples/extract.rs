@@ -0,0 +1,53 @@
+use argos_macros::{route, register};
+use serde_json;
+use serde::{Deserialize, Serialize};
+use argos::{server::Server, error::ReturnError, extract::{Path, Query, Json, Header, State}};
+
+#[derive(Deserialize)]
+pub struct Paging {
+    page: u32,
+    size: Option<u32>,
+}
+
+pub struct Config {
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct User {
+    id: u64,
+    name: String,
+}
+
+#[route(GET, path = "/api/users/:id(\\d+)", formatter = "json")]
+pub async fn get_user(Path((id,)): Path<(u64,)>) -> Result<User, ReturnError<String>> {
+    Ok(User { id, name: format!("user-{}", id) })
+}
+
+#[route(GET, path = "/api/users", formatter = "text")]
+pub async fn list_users(Query(paging): Query<Paging>) -> Result<String, ReturnError<String>> {
+    Ok(format!("page {} of size {}", paging.page, paging.size.unwrap_or(20)))
+}
+
+#[route(PUT, path = "/api/users/:id(\\d+)", formatter = "json")]
+pub async fn update_user(
+    Path((id,)): Path<(u64,)>,
+    token: Header<"x-token">,
+    Json(user): Json<User>,
+    config: State<Config>,
+) -> Result<User, ReturnError<String>> {
+    if token.as_str() != config.token {
+        return Err(ReturnError::new(401, "not authorized".to_string()));
+    }
+    Ok(User { id, name: user.name })
+}
+
+#[tokio::main]
+async fn main() {
+    let server = Server::builder(([127, 0, 0, 1], 3000).into())
+        .state(Config { token: "secret".to_string() })
+        .build()
+        .await
+        .unwrap();
+    server.start().await.unwrap();
+}
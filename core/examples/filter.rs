@@ -1,24 +1,34 @@
 
-use argos::{request::HttpRequest, error::ReturnError, server::Server, request::HeaderValue, Chain};
+use argos::{request::HttpRequest, error::ReturnError, server::Server, request::HeaderValue, Chain, extract::Extension};
 use argos_macros::{route, filter, register};
 
+#[derive(Clone, Debug)]
+pub struct User {
+    name: String,
+}
+
 #[route(GET, path = "/api/hello", formatter = "text")]
-pub fn hello(req: HttpRequest) -> Result<String, ReturnError<String>> {
+pub async fn hello(req: HttpRequest) -> Result<String, ReturnError<String>> {
     let url_params = req.url_params();
     let name = url_params.get("name");
     let path_params = req.path_params();
-    use std::{thread, time};
-    let millis = time::Duration::from_millis(800);
-    thread::sleep(millis);
+    tokio::time::sleep(std::time::Duration::from_millis(800)).await;
     Ok(format!("hello {:?}, {:?}, {:?}", name, path_params, req.attributes()))
 }
 
+#[route(GET, path = "/api/hello/me", formatter = "text")]
+pub async fn hello_me(Extension(user): Extension<User>) -> Result<String, ReturnError<String>> {
+    Ok(format!("hello {}", user.name))
+}
+
 #[filter(path_pattern="/api/hello.*", order=1)]
 pub fn path_filter(mut req: HttpRequest) -> Chain {
     let attr = req.attributes_mut();
     attr.insert("kk".to_string(), "value".to_string());
     // let _method = req.method_mut();
-    if req.headers().contains_key("token") {
+    if let Some(token) = req.headers().get("token").cloned() {
+        let name = token.to_str().unwrap_or_default().to_string();
+        req.extensions_mut().insert(User { name });
         Chain::Continune(req)
     } else {
         let mut return_err = ReturnError::new(
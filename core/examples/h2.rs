@@ -23,13 +23,11 @@ impl Display for MyError {
 }
 
 #[route(GET, path = "/api/hello", formatter = "json")]
-pub fn hello(req: HttpRequest) -> Result<Hello, ReturnError<MyError>> {
+pub async fn hello(req: HttpRequest) -> Result<Hello, ReturnError<MyError>> {
     let url_params = req.url_params();
     let name = url_params.get("name");
     // let path_params = req.path_params();
-    use std::{thread, time};
-    let millis = time::Duration::from_millis(800);
-    thread::sleep(millis);
+    tokio::time::sleep(std::time::Duration::from_millis(800)).await;
     match name {
         Some(name) => Ok(Hello { name: name.to_string(), greeting: "hello".to_string() }),
         None => Err(
@@ -3,7 +3,7 @@ use std::fmt::Display;
 use argos_macros::{route, register};
 use serde_json;
 use serde::{Deserialize, Serialize};
-use argos::{server::Server, request::HttpRequest, error::ReturnError};
+use argos::{server::Server, request::HttpRequest, error::ReturnError, access_log::{AccessLog, LogFormat}};
 
 #[derive(Serialize, Deserialize)]
 pub struct Hello {
@@ -24,13 +24,11 @@ impl Display for MyError {
 }
 
 #[route(GET, path = "/api/hello", formatter = "json")]
-pub fn hello(req: HttpRequest) -> Result<Hello, ReturnError<MyError>> {
+pub async fn hello(req: HttpRequest) -> Result<Hello, ReturnError<MyError>> {
     let url_params = req.url_params();
     let name = url_params.get("name");
     // let path_params = req.path_params();
-    use std::{thread, time};
-    let millis = time::Duration::from_millis(800);
-    thread::sleep(millis);
+    tokio::time::sleep(std::time::Duration::from_millis(800)).await;
     match name {
         Some(name) => Ok(Hello { name: name.to_string(), greeting: "hello".to_string() }),
         None => Err(
@@ -44,7 +42,9 @@ pub fn hello(req: HttpRequest) -> Result<Hello, ReturnError<MyError>> {
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt::init();
     let server = Server::builder(([127, 0, 0, 1], 3000).into())
+        .access_log(AccessLog::new(LogFormat::Combined))
         .build()
         .await
         .unwrap();
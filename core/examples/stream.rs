@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use argos_macros::{route, register};
+use argos::{server::Server, request::HttpRequest, response::{Body, Response}, error::ReturnError};
+use futures_util::stream;
+
+/// Sends one line per second, the client sees each as soon as it is written.
+#[route(GET, path = "/api/ticks", formatter = "response")]
+pub async fn ticks(_req: HttpRequest) -> Result<Response<Body>, ReturnError<String>> {
+    let ticks = stream::unfold(0, |tick| async move {
+        if tick == 5 {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Some((format!("tick {}\n", tick).into(), tick + 1))
+    });
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain")
+        .body(Body::from_stream(ticks))
+        .unwrap())
+}
+
+/// Streams a file from disk without reading it into memory first.
+#[route(GET, path = "/api/download", formatter = "response")]
+pub async fn download(_req: HttpRequest) -> Result<Response<Body>, ReturnError<String>> {
+    let file = tokio::fs::File::open("Cargo.toml").await
+        .map_err(|err| ReturnError::new(404, err.to_string()))?;
+    let body = Body::file(file).await
+        .map_err(|err| ReturnError::new(500, err.to_string()))?;
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain")
+        .header("Content-Disposition", "attachment; filename=\"Cargo.toml\"")
+        .body(body)
+        .unwrap())
+}
+
+#[tokio::main]
+async fn main() {
+    // run from `core`: http://127.0.0.1:3000/assets/ serves examples/public/index.html
+    let server = Server::builder(([127, 0, 0, 1], 3000).into())
+        .static_dir("/assets", "examples/public")
+        .build()
+        .await
+        .unwrap();
+    server.start().await.unwrap();
+}
@@ -0,0 +1,302 @@
+//! Access log written once per answered request.
+//!
+//! ```ignore
+//! let server = Server::builder(([127, 0, 0, 1], 3000).into())
+//!     .access_log(AccessLog::new(LogFormat::Combined).writer(File::create("access.log")?))
+//!     .build()
+//!     .await?;
+//! ```
+use std::{
+    fmt::Write as _,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    sync::{mpsc::{self, SyncSender, TrySendError}, Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::request::{Body, Method, Version};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format: `host ident user [time] "request" status bytes`.
+    Common,
+    /// The Common Log Format followed by the `Referer` and `User-Agent` headers.
+    Combined,
+    /// One JSON object per line, including the latency in milliseconds.
+    Json,
+}
+
+/// Lines waiting for the writer thread, more are dropped with a warning.
+const QUEUED_LINES: usize = 8192;
+
+/// Formats and writes access log lines, to stdout unless another writer is set.
+///
+/// The lines are written and flushed by a thread of their own, so a slow disk
+/// or pipe does not hold up the requests.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    lines: Arc<OnceLock<SyncSender<String>>>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat) -> Self {
+        Self {
+            format,
+            writer: Arc::new(Mutex::new(Box::new(std::io::stdout()))),
+            lines: Arc::default(),
+        }
+    }
+
+    /// Writes the log lines to `writer`, e.g. a `File`.
+    pub fn writer<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.writer = Arc::new(Mutex::new(Box::new(writer)));
+        self.lines = Arc::default();
+        self
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    pub(crate) fn write(&self, entry: &AccessEntry, status: u16, bytes: Option<u64>, latency: Duration) {
+        let line = match self.format {
+            LogFormat::Common => entry.common(status, bytes),
+            LogFormat::Combined => entry.combined(status, bytes),
+            LogFormat::Json => entry.json(status, bytes, latency),
+        };
+        let lines = self.lines.get_or_init(|| self.spawn_writer());
+        match lines.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => tracing::warn!("access log writer is behind, dropped a line"),
+            // the writer thread could not be started, reported by `spawn_writer`
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// Starts the thread writing the lines, on the first request. The buffer
+    /// is flushed whenever no more lines are queued.
+    fn spawn_writer(&self) -> SyncSender<String> {
+        let (sender, receiver) = mpsc::sync_channel::<String>(QUEUED_LINES);
+        let writer = self.writer.clone();
+        let spawned = std::thread::Builder::new().name("argos-access-log".to_string()).spawn(move || {
+            let mut writer = writer.lock().unwrap_or_else(|err| err.into_inner());
+            let mut writer = BufWriter::new(&mut **writer);
+            while let Ok(line) = receiver.recv() {
+                let written = std::iter::once(line)
+                    .chain(receiver.try_iter())
+                    .try_for_each(|line| writeln!(writer, "{}", line))
+                    .and_then(|_| writer.flush());
+                if let Err(err) = written {
+                    tracing::warn!(error = %err, "failed to write access log");
+                }
+            }
+        });
+        if let Err(err) = spawned {
+            tracing::error!(error = %err, "failed to start the access log writer");
+        }
+        sender
+    }
+}
+
+/// Request details captured before the request is handed to the filters and
+/// the handler.
+pub(crate) struct AccessEntry {
+    time: SystemTime,
+    remote_addr: Option<SocketAddr>,
+    method: Method,
+    uri: String,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessEntry {
+    pub(crate) fn new(req: &hyper::Request<Body>, remote_addr: Option<SocketAddr>) -> Self {
+        let header = |name| {
+            req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+        };
+        Self {
+            time: SystemTime::now(),
+            remote_addr,
+            method: req.method().clone(),
+            uri: req.uri().path_and_query().map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string()),
+            version: req.version(),
+            referer: header(hyper::header::REFERER),
+            user_agent: header(hyper::header::USER_AGENT),
+        }
+    }
+
+    fn host(&self) -> String {
+        self.remote_addr.map_or_else(|| "-".to_string(), |addr| addr.ip().to_string())
+    }
+
+    fn common(&self, status: u16, bytes: Option<u64>) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {:?}\" {} {}",
+            self.host(),
+            clf_time(self.time),
+            self.method,
+            self.uri,
+            self.version,
+            status,
+            bytes.map_or_else(|| "-".to_string(), |bytes| bytes.to_string()),
+        )
+    }
+
+    fn combined(&self, status: u16, bytes: Option<u64>) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.common(status, bytes),
+            escape(self.referer.as_deref().unwrap_or("-")),
+            escape(self.user_agent.as_deref().unwrap_or("-")),
+        )
+    }
+
+    fn json(&self, status: u16, bytes: Option<u64>, latency: Duration) -> String {
+        serde_json::json!({
+            "time": rfc3339_time(self.time),
+            "remote_addr": self.remote_addr.map(|addr| addr.to_string()),
+            "method": self.method.as_str(),
+            "uri": self.uri,
+            "version": format!("{:?}", self.version),
+            "status": status,
+            "bytes": bytes,
+            "referer": self.referer,
+            "user_agent": self.user_agent,
+            "latency_ms": latency.as_secs_f64() * 1000.0,
+        })
+        .to_string()
+    }
+}
+
+/// Escapes quotes and control characters in a quoted log field.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// UTC date and time of `time` as (year, month, day, hour, minute, second).
+fn civil_time(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// `10/Oct/2000:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year, hour, minute, second,
+    )
+}
+
+/// `2000-10-10T13:55:36Z`
+fn rfc3339_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil_time(time);
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks every write until `release` is dropped, like a stalled disk.
+    struct Stalled {
+        release: mpsc::Receiver<()>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let _ = self.release.recv();
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn entry() -> AccessEntry {
+        AccessEntry {
+            time: UNIX_EPOCH,
+            remote_addr: None,
+            method: Method::GET,
+            uri: "/".to_string(),
+            version: Version::HTTP_11,
+            referer: None,
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn lines_are_written_without_blocking_the_caller() {
+        let (release, stalled) = mpsc::channel();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let log = AccessLog::new(LogFormat::Common).writer(Stalled { release: stalled, written: written.clone() });
+
+        let started = std::time::Instant::now();
+        for status in [200, 404, 500] {
+            log.write(&entry(), status, None, Duration::ZERO);
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(written.lock().unwrap().is_empty());
+
+        drop(release);
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while written.lock().unwrap().split(|b| *b == b'\n').count() < 4 {
+            assert!(std::time::Instant::now() < deadline, "the lines were not written");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert_eq!(written.lines().map(|line| &line[line.len() - 5..]).collect::<Vec<_>>(), ["200 -", "404 -", "500 -"]);
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn civil_time_of_known_dates() {
+        assert_eq!(civil_time(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil_time(at(971_186_136)), (2000, 10, 10, 13, 55, 36));
+        // leap days, including the one of a century divisible by 400
+        assert_eq!(civil_time(at(951_782_400)), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(civil_time(at(1_709_251_199)), (2024, 2, 29, 23, 59, 59));
+        assert_eq!(civil_time(at(4_107_542_400)), (2100, 3, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn formats_log_times() {
+        assert_eq!(clf_time(at(971_186_136)), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(rfc3339_time(at(971_186_136)), "2000-10-10T13:55:36Z");
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use crate::request::HeaderMap;
+use crate::{request::HeaderMap, response::{Body, Response}};
 
 pub type Error = hyper::Error;
 
@@ -20,4 +20,15 @@ impl <B: Display> ReturnError<B> {
             headers: HeaderMap::new(),
         }
     }
+
+    /// Builds a plain text response carrying the status code, headers and body.
+    pub fn into_response(self) -> Response<Body> {
+        let mut builder = Response::builder().status(self.response_code);
+        for (k, v) in self.headers.iter() {
+            builder.headers_mut().unwrap().insert(k, v.clone());
+        }
+        builder
+            .body(Body::from(self.response_body.to_string()))
+            .unwrap()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+//! Typed handler parameters.
+//!
+//! A `#[route]` function may take any number of extractors instead of (or
+//! besides) the `HttpRequest` itself:
+//!
+//! ```ignore
+//! #[route(POST, path = "/api/users/:id", formatter = "json")]
+//! pub async fn update(
+//!     Path((id,)): Path<(u64,)>,
+//!     Query(opts): Query<UpdateOptions>,
+//!     token: Header<"x-token">,
+//!     Json(user): Json<User>,
+//!     db: State<Database>,
+//! ) -> Result<User, ReturnError<String>> {
+//!     // ...
+//! }
+//! ```
+//!
+//! When an extractor fails the handler is not called and the error is
+//! returned instead, usually with status `400`.
+use std::{future::Future, ops::Deref, pin::Pin, sync::Arc};
+
+use serde::de::DeserializeOwned;
+
+use crate::{error::ReturnError, multipart::Multipart, request::{HeaderValue, HttpRequest}};
+
+mod de;
+
+pub type ExtractFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ReturnError<String>>> + Send + 'a>>;
+
+/// Types that can be created from a request, used as `#[route]` handler parameters.
+pub trait FromRequest: Sized {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self>;
+}
+
+/// Makes an extractor optional: a failed extraction gives `None`.
+impl<T: FromRequest + Send> FromRequest for Option<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        Box::pin(async move { Ok(T::from_request(req).await.ok()) })
+    }
+}
+
+/// Path parameters, deserialized in pattern order into a tuple or by name into a struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Path<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        let result = T::deserialize(de::PathDeserializer::new(req.path_param_list()))
+            .map(Path)
+            .map_err(|err| ReturnError::new(400, format!("invalid path parameters: {}", err)));
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// The query string, see [`HttpRequest::query`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Query<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        let result = req.query().map(Query);
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// A JSON request body, see [`HttpRequest::json`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Json<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        Box::pin(async move { req.json().await.map(Json) })
+    }
+}
+
+/// A `application/x-www-form-urlencoded` request body, see [`HttpRequest::form`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Form<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        Box::pin(async move { req.form().await.map(Form) })
+    }
+}
+
+impl FromRequest for Multipart {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        Box::pin(std::future::ready(req.multipart()))
+    }
+}
+
+/// Shared state registered with `ServerBuilder::state`, see [`HttpRequest::state`].
+///
+/// A handler asking for a type that was never registered is answered with `500`.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        let result = match req.state::<T>() {
+            Some(state) => Ok(State(state)),
+            None => Err(ReturnError::new(500, format!("state `{}` is not registered", std::any::type_name::<T>()))),
+        };
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// A typed value a filter attached with `req.extensions_mut().insert(..)`.
+///
+/// A handler asking for a missing extension is answered with `500`.
+#[derive(Debug, Clone)]
+pub struct Extension<T>(pub T);
+
+impl<T> Deref for Extension<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(req: &mut HttpRequest) -> ExtractFuture<'_, Self> {
+        let result = match req.extensions().get::<T>() {
+            Some(value) => Ok(Extension(value.clone())),
+            None => Err(ReturnError::new(500, format!("extension `{}` is missing", std::any::type_name::<T>()))),
+        };
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// A request header, written as `Header<"x-token">` in a handler signature.
+///
+/// `#[route]` rewrites the parameter type to this struct and extracts the
+/// named header; a missing or non-UTF-8 header is rejected with `400`, unless
+/// the parameter is an `Option<Header<"...">>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header(pub String);
+
+impl Header {
+    pub fn extract(req: &HttpRequest, name: &str) -> Result<Self, ReturnError<String>> {
+        match Self::extract_optional(req, name)? {
+            Some(header) => Ok(header),
+            None => Err(ReturnError::new(400, format!("missing header `{}`", name))),
+        }
+    }
+
+    pub fn extract_optional(req: &HttpRequest, name: &str) -> Result<Option<Self>, ReturnError<String>> {
+        match req.headers().get(name).map(HeaderValue::to_str) {
+            Some(Ok(value)) => Ok(Some(Header(value.to_string()))),
+            Some(Err(_)) => Err(ReturnError::new(400, format!("header `{}` is not valid UTF-8", name))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
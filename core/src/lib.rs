@@ -1,25 +1,63 @@
-use std::sync::RwLock;
+// `ReturnError` carries its headers and is what every handler returns,
+// boxing it would break them
+#![allow(clippy::result_large_err)]
+
+use std::sync::{Arc, RwLock};
 use std::{collections::HashMap, fmt::Display, hash::Hash};
 
 use error::ReturnError;
 use lazy_static::lazy_static;
-use bytes::Bytes;
-
-use http_body_util::Full;
-
-use crate::request::HttpRequest;
+use regex::Regex;
+use crate::{request::HttpRequest, response::Body};
 
 pub mod request;
 pub mod response;
 pub mod server;
 pub mod support;
 pub mod error;
+pub mod extract;
 pub mod util;
+pub mod access_log;
+pub mod tls;
+pub mod multipart;
+pub mod static_files;
+mod listener;
+mod router;
+mod state;
 
+/// A route path pattern.
+///
+/// Besides literal segments a pattern may contain:
+/// - `:name` parameters matching one non-empty segment,
+/// - `:name(regex)` parameters that only match when the whole segment matches `regex`,
+/// - a trailing `*name` catch-all capturing the rest of the path (possibly empty).
+///
+/// A single trailing slash is ignored, both in patterns and in request paths.
 pub struct Path {
     inner: String,
 }
 
+pub(crate) enum Segment {
+    Static(String),
+    Param {
+        name: String,
+        constraint: Option<Regex>,
+    },
+    CatchAll(String),
+}
+
+impl Segment {
+    pub(crate) fn matches(&self, segment: &str) -> bool {
+        match self {
+            Segment::Static(s) => s == segment,
+            Segment::Param { constraint, .. } => {
+                !segment.is_empty() && constraint.as_ref().map_or(true, |reg| reg.is_match(segment))
+            }
+            Segment::CatchAll(_) => true,
+        }
+    }
+}
+
 impl Path {
     pub fn new(path: &str) -> Self {
         Self {
@@ -27,31 +65,85 @@ impl Path {
         }
     }
 
+    pub(crate) fn segments(&self) -> Result<Vec<Segment>, String> {
+        let parts: Vec<&str> = split_path(&self.inner).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(param) = part.strip_prefix(':') {
+                match param.find('(') {
+                    Some(open) if param.ends_with(')') => {
+                        let pattern = &param[open + 1..param.len() - 1];
+                        let constraint = Regex::new(&format!("^(?:{})$", pattern))
+                            .map_err(|err| format!("invalid constraint in path `{}`: {}", self.inner, err))?;
+                        Segment::Param {
+                            name: param[..open].to_string(),
+                            constraint: Some(constraint),
+                        }
+                    }
+                    Some(_) => return Err(format!("unclosed constraint in path `{}`", self.inner)),
+                    None => Segment::Param {
+                        name: param.to_string(),
+                        constraint: None,
+                    },
+                }
+            } else if let Some(name) = part.strip_prefix('*') {
+                if i != parts.len() - 1 {
+                    return Err(format!("catch-all `*{}` must be the last segment of path `{}`", name, self.inner));
+                }
+                Segment::CatchAll(name.to_string())
+            } else {
+                Segment::Static(part.to_string())
+            };
+            segments.push(segment);
+        }
+        Ok(segments)
+    }
+
     pub fn pattern_match(&self, other: &String) -> Option<HashMap<String, String>> {
+        let segments = self.segments().ok()?;
+        let parts: Vec<_> = split_path(other).map(decode_segment).collect();
         let mut path_params = HashMap::new();
-        let mut iter1 = self.inner.split("/");
-        let mut iter2 = other.split("/");
-        loop {
-            let seg1 = iter1.next();
-            let seg2 = iter2.next();
-            if seg1.is_none() && seg2.is_none() {
-                break;
+        for (i, segment) in segments.iter().enumerate() {
+            if let Segment::CatchAll(name) = segment {
+                path_params.insert(name.clone(), parts.get(i..).map(|rest| rest.join("/")).unwrap_or_default());
+                return Some(path_params);
             }
-            if seg1.is_none() || seg2.is_none() {
+            let part = parts.get(i)?;
+            if !segment.matches(part) {
                 return None;
             }
-            let seg1 = seg1.unwrap();
-            let seg2 = seg2.unwrap();
-            if seg1.starts_with(":") {
-                path_params.insert(seg1[1..].to_string(), seg2.to_string());
-            } else if seg1 != seg2 {
-                return None;
+            if let Segment::Param { name, .. } = segment {
+                path_params.insert(name.clone(), part.to_string());
             }
         }
+        if parts.len() != segments.len() {
+            return None;
+        }
         Some(path_params)
     }
 }
 
+/// Splits a path on `/`, ignoring a single trailing slash.
+pub(crate) fn split_path(path: &str) -> std::str::Split<'_, char> {
+    let path = if path.len() > 1 {
+        path.strip_suffix('/').unwrap_or(path)
+    } else {
+        path
+    };
+    path.split('/')
+}
+
+/// Percent-decodes a path segment, before it is matched and extracted.
+pub(crate) fn decode_segment(segment: &str) -> std::borrow::Cow<'_, str> {
+    percent_encoding::percent_decode_str(segment).decode_utf8_lossy()
+}
+
+/// Percent-decodes a whole path for the filter predicates, so they see the
+/// same segments the router matches: `/%61dmin` is `/admin` for both.
+pub(crate) fn decode_path(path: &str) -> std::borrow::Cow<'_, str> {
+    percent_encoding::percent_decode_str(path).decode_utf8_lossy()
+}
+
 impl Eq for Path {}
 
 impl PartialEq for Path {
@@ -93,11 +185,11 @@ pub struct RouteInfo {
     method: String,
     path: Path,
     handler: Box<dyn Fn(HttpRequest) -> 
-    std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Full<Bytes>>, hyper::Error>> + Send>> + Send + Sync>,
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Body>, hyper::Error>> + Send>> + Send + Sync>,
 }
 
 impl  RouteInfo {
-    pub fn new(method: String, path: Path, handler: Box<dyn Fn(HttpRequest) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Full<Bytes>>, hyper::Error>> + Send>> + Send + Sync>) -> Self {
+    pub fn new(method: String, path: Path, handler: Box<dyn Fn(HttpRequest) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Body>, hyper::Error>> + Send>> + Send + Sync>) -> Self {
         Self {
             method,
             path,
@@ -113,11 +205,13 @@ impl  RouteInfo {
         &self.path
     }
 
-    pub fn handler(&self) -> &Box<dyn Fn(HttpRequest) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Full<Bytes>>, hyper::Error>> + Send>> + Send + Sync> {
+    pub fn handler(&self) -> &Box<dyn Fn(HttpRequest) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<hyper::Response<Body>, hyper::Error>> + Send>> + Send + Sync> {
         &self.handler
     }
 }
 
+// filters build the variants directly, boxing the request would break them
+#[allow(clippy::large_enum_variant)]
 pub enum Chain {
     Continune(HttpRequest),
     Reject(ReturnError<String>),
@@ -135,6 +229,15 @@ impl Predicate {
             _ => panic!("unsupported predicate type: {}", t),
         }
     }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            Self::PathPattern(pattern) => match Regex::new(pattern) {
+                Ok(reg) => reg.is_match(path),
+                Err(_) => false,
+            },
+        }
+    }
 }
     
 pub struct FilterInfo {
@@ -170,7 +273,46 @@ impl FilterInfo {
 }
 
 lazy_static! {
-    pub static ref ROUTE_TABLE: RwLock<Vec<RouteInfo>> = RwLock::new(Vec::new());
+    pub static ref ROUTE_TABLE: RwLock<Vec<Arc<RouteInfo>>> = RwLock::new(Vec::new());
     pub static ref FILTER_TABLE: RwLock<Vec<FilterInfo>> = RwLock::new(Vec::new());
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(path: &str, other: &str) -> Option<Vec<(String, String)>> {
+        let mut params: Vec<_> = Path::new(path).pattern_match(&other.to_string())?.into_iter().collect();
+        params.sort();
+        Some(params)
+    }
+
+    fn param(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn pattern_match_params_and_catch_all() {
+        assert_eq!(params("/users/:id", "/users/12"), Some(vec![param("id", "12")]));
+        assert_eq!(params("/users/:id", "/users/12/"), Some(vec![param("id", "12")]));
+        assert_eq!(params("/users/:id", "/users/"), None);
+        assert_eq!(params("/users/:id", "/users/12/posts"), None);
+        assert_eq!(params("/files/*rest", "/files/a/b%20c"), Some(vec![param("rest", "a/b c")]));
+        assert_eq!(params("/files/*rest", "/files"), Some(vec![param("rest", "")]));
+    }
+
+    #[test]
+    fn pattern_match_checks_constraints() {
+        assert_eq!(params("/users/:id(\\d+)", "/users/12"), Some(vec![param("id", "12")]));
+        assert_eq!(params("/users/:id(\\d+)", "/users/12a"), None);
+        // the whole segment has to match
+        assert_eq!(params("/users/:id(\\d+|me)", "/users/mine"), None);
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        assert!(Path::new("/files/*rest/more").segments().is_err());
+        assert!(Path::new("/users/:id(\\d+").segments().is_err());
+        assert!(Path::new("/users/:id([)").segments().is_err());
+    }
+}
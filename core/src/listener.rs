@@ -0,0 +1,104 @@
+use std::{io, net::SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use futures_util::{stream, Stream};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Where a `Server` accepts connections, see `ServerBuilder::bind`,
+/// `ServerBuilder::listener` and `ServerBuilder::unix`.
+#[derive(Debug)]
+pub(crate) enum Bind {
+    Addr(SocketAddr),
+    Listener(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Bind {
+    pub(crate) async fn bind(&self) -> io::Result<Listener> {
+        match self {
+            Bind::Addr(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            Bind::Listener(listener) => {
+                // the builder keeps its listener, so that it can be built again
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                // a socket left behind by an earlier run would fail the bind,
+                // it is only removed when no server answers on it anymore
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    match UnixStream::connect(path).await {
+                        Ok(_) => {
+                            let message = format!("another server is listening on `{}`", path.display());
+                            return Err(io::Error::new(io::ErrorKind::AddrInUse, message));
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, remote_addr) = listener.accept().await?;
+                Ok(Accepted::Tcp(stream, remote_addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Accepted::Unix(stream))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Connections accepted on any of the listeners, in the order they arrive.
+pub(crate) fn incoming(listeners: Vec<Listener>) -> impl Stream<Item = io::Result<Accepted>> + Unpin {
+    stream::select_all(listeners.into_iter().map(|listener| {
+        Box::pin(stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await;
+            Some((accepted, listener))
+        }))
+    }))
+}
@@ -0,0 +1,188 @@
+//! Streaming `multipart/form-data` bodies, see [`HttpRequest::multipart`].
+//!
+//! The file name is chosen by the client and may contain `..` or `/`, so
+//! uploads are stored under a name the server generates:
+//!
+//! ```ignore
+//! static UPLOADS: AtomicU64 = AtomicU64::new(0);
+//!
+//! #[route(POST, path = "/api/upload", formatter = "text")]
+//! pub async fn upload(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+//!     let mut multipart = req.multipart()?;
+//!     while let Some(mut field) = multipart.next_field().await? {
+//!         if field.file_name().is_some() {
+//!             let path = format!("uploads/{}", UPLOADS.fetch_add(1, Ordering::Relaxed));
+//!             let mut file = tokio::fs::File::create(path).await
+//!                 .map_err(|err| ReturnError::new(500, err.to_string()))?;
+//!             field.pipe_to(&mut file).await?;
+//!         }
+//!     }
+//!     Ok("uploaded".to_string())
+//! }
+//! ```
+use std::fmt::Debug;
+
+use bytes::Bytes;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    error::ReturnError,
+    request::{HeaderMap, HttpRequest},
+};
+
+/// Size limits of a multipart body, in bytes.
+///
+/// Exceeding either limit fails the read with `413`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MultipartLimits {
+    per_part: Option<u64>,
+    total: Option<u64>,
+}
+
+impl MultipartLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum size of the content of a single part.
+    pub fn per_part(mut self, limit: u64) -> Self {
+        self.per_part = Some(limit);
+        self
+    }
+
+    /// Maximum size of the whole body, boundaries and part headers included.
+    pub fn total(mut self, limit: u64) -> Self {
+        self.total = Some(limit);
+        self
+    }
+
+    fn constraints(&self) -> multer::Constraints {
+        let mut size_limit = multer::SizeLimit::new();
+        if let Some(limit) = self.per_part {
+            size_limit = size_limit.per_field(limit);
+        }
+        if let Some(limit) = self.total {
+            size_limit = size_limit.whole_stream(limit);
+        }
+        multer::Constraints::new().size_limit(size_limit)
+    }
+}
+
+/// A `multipart/form-data` body, read part by part as it arrives.
+pub struct Multipart {
+    inner: multer::Multipart<'static>,
+}
+
+impl Multipart {
+    /// Parses the body of `req`, taking the boundary from its `Content-Type`.
+    /// Fails with `415` when the request is not `multipart/form-data`.
+    pub(crate) fn new(req: &mut HttpRequest, limits: MultipartLimits) -> Result<Self, ReturnError<String>> {
+        let boundary = req.headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.trim_start().to_ascii_lowercase().starts_with("multipart/form-data"))
+            .and_then(|v| multer::parse_boundary(v).ok())
+            .ok_or_else(|| ReturnError::new(415, "expected a multipart/form-data body with a boundary".to_string()))?;
+        let body = req.body_stream()?;
+        Ok(Self {
+            inner: multer::Multipart::with_constraints(body, boundary, limits.constraints()),
+        })
+    }
+
+    /// The next part, or `None` once the body is complete. The previous part
+    /// must have been dropped; its unread content is skipped.
+    pub async fn next_field(&mut self) -> Result<Option<Field>, ReturnError<String>> {
+        let field = self.inner.next_field().await.map_err(into_return_error)?;
+        Ok(field.map(|inner| Field { inner }))
+    }
+}
+
+impl Debug for Multipart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Multipart").finish_non_exhaustive()
+    }
+}
+
+/// A single part of a multipart body: a form field, or a file when it has a
+/// file name.
+pub struct Field {
+    inner: multer::Field<'static>,
+}
+
+impl Field {
+    /// The `name` parameter of the part's `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    /// The `filename` parameter of the part's `Content-Disposition`, as sent
+    /// by the client. It is not sanitized and must not be used as a path as is.
+    pub fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    /// The `Content-Type` of the part.
+    pub fn content_type(&self) -> Option<&str> {
+        self.inner.content_type().map(|mime| mime.as_ref())
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// The next chunk of the content, or `None` at the end of the part.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, ReturnError<String>> {
+        self.inner.chunk().await.map_err(into_return_error)
+    }
+
+    /// Reads the whole content of the part.
+    pub async fn bytes(self) -> Result<Bytes, ReturnError<String>> {
+        self.inner.bytes().await.map_err(into_return_error)
+    }
+
+    /// Reads the content of the part as text, decoded with the charset of its
+    /// `Content-Type`, UTF-8 by default.
+    pub async fn text(self) -> Result<String, ReturnError<String>> {
+        self.inner.text().await.map_err(into_return_error)
+    }
+
+    /// Streams the content of the part into `writer` and flushes it, returning
+    /// the number of bytes written. Fails with `500` when `writer` fails.
+    pub async fn pipe_to<W>(&mut self, writer: &mut W) -> Result<u64, ReturnError<String>>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut written = 0;
+        while let Some(chunk) = self.chunk().await? {
+            writer.write_all(&chunk).await
+                .map_err(|err| ReturnError::new(500, format!("failed to write multipart field: {}", err)))?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await
+            .map_err(|err| ReturnError::new(500, format!("failed to write multipart field: {}", err)))?;
+        Ok(written)
+    }
+}
+
+impl Debug for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Field")
+            .field("name", &self.name())
+            .field("file_name", &self.file_name())
+            .field("content_type", &self.content_type())
+            .finish_non_exhaustive()
+    }
+}
+
+fn into_return_error(err: multer::Error) -> ReturnError<String> {
+    match err {
+        multer::Error::FieldSizeExceeded { limit, field_name } => ReturnError::new(
+            413,
+            format!("multipart field {} exceeds the limit of {} bytes", field_name.as_deref().unwrap_or("<unnamed>"), limit),
+        ),
+        multer::Error::StreamSizeExceeded { limit } => {
+            ReturnError::new(413, format!("request body exceeds the limit of {} bytes", limit))
+        }
+        err => ReturnError::new(400, format!("invalid multipart body: {}", err)),
+    }
+}
@@ -1,22 +1,44 @@
-
 // pub struct HttpRequest {
 //     inner: hyper::Request<hyper::body::Incoming>,
 // }
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, io, net::SocketAddr, pin::Pin, sync::Arc, task::{Context, Poll}};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{error::ReturnError, multipart::{Multipart, MultipartLimits}, state::StateMap};
+
+/// Default maximum number of bytes read by the buffered body accessors.
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
 
 // pub type HttpRequest = hyper::Request<hyper::body::Incoming>;
 pub struct HttpRequest {
-    inner: hyper::Request<hyper::body::Incoming>,
+    inner: hyper::Request<RequestBody>,
     path_params: HashMap<String, String>,
+    path_param_list: Vec<(String, String)>,
     attributes: HashMap<String, String>,
+    body_limit: usize,
+    state: Arc<StateMap>,
+    conn: Arc<ConnectionInfo>,
 }
 pub type Method = hyper::Method;
 pub type HeaderMap = hyper::HeaderMap<HeaderValue>;
 pub type HeaderValue = hyper::header::HeaderValue;
 pub type Version = hyper::Version;
+pub type Uri = hyper::Uri;
+pub type Extensions = hyper::http::Extensions;
 pub type Body = hyper::body::Incoming;
 
+enum RequestBody {
+    Incoming(Body),
+    Buffered(Bytes),
+    Consumed,
+}
+
 // pub trait RequestExt {
 //     fn url_params(&self) -> HashMap<String, String>;
 // }
@@ -25,16 +47,27 @@ impl HttpRequest {
 
     pub fn new(req: hyper::Request<hyper::body::Incoming>) -> Self {
         Self {
-            inner: req,
+            inner: req.map(RequestBody::Incoming),
             path_params: HashMap::new(),
+            path_param_list: Vec::new(),
             attributes: HashMap::new(),
+            body_limit: DEFAULT_BODY_LIMIT,
+            state: Arc::default(),
+            conn: Arc::default(),
         }
     }
 
     pub fn set_path_params(&mut self, path_params: HashMap<String, String>) {
+        self.path_param_list = path_params.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
         self.path_params = path_params;
     }
 
+    /// Sets the path parameters matched by the router, in pattern order.
+    pub(crate) fn set_path_param_list(&mut self, path_params: Vec<(String, String)>) {
+        self.path_params = path_params.iter().cloned().collect();
+        self.path_param_list = path_params;
+    }
+
     pub fn method(&self) -> &Method {
         self.inner.method()
     }
@@ -43,6 +76,14 @@ impl HttpRequest {
         self.inner.method_mut()
     }
 
+    pub fn version(&self) -> Version {
+        self.inner.version()
+    }
+
+    pub fn uri(&self) -> &Uri {
+        self.inner.uri()
+    }
+
     pub fn path(&self) -> &str {
         self.inner.uri().path()
     }
@@ -55,13 +96,36 @@ impl HttpRequest {
         &self.path_params
     }
 
+    pub(crate) fn path_param_list(&self) -> &[(String, String)] {
+        &self.path_param_list
+    }
+
+    /// Decoded query parameters; for a repeated key the last value wins, as it
+    /// always did, see [`query_params`](Self::query_params) for all of them.
     pub fn url_params(&self) -> HashMap<String, String> {
-        self.inner.uri().query().unwrap_or("").split("&").map(|kv| {
-            let mut iter = kv.split("=");
-            let key = iter.next().unwrap_or("");
-            let value = iter.next().unwrap_or("");
-            (key.to_string(), value.to_string())
-        }).collect()
+        self.query_params().iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    /// Decoded query parameters in request order, keeping repeated keys.
+    pub fn query_params(&self) -> QueryParams {
+        QueryParams::parse(self.inner.uri().query().unwrap_or(""))
+    }
+
+    /// Deserializes the query string, e.g. into a struct with `#[derive(Deserialize)]`.
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, ReturnError<String>> {
+        serde_urlencoded::from_str(self.inner.uri().query().unwrap_or(""))
+            .map_err(|err| ReturnError::new(400, format!("invalid query string: {}", err)))
+    }
+
+    /// Typed values attached to the request, e.g. the user authenticated by a
+    /// filter. Prefer this over [`attributes`](Self::attributes) for anything
+    /// that is not a plain string.
+    pub fn extensions(&self) -> &Extensions {
+        self.inner.extensions()
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        self.inner.extensions_mut()
     }
 
     pub fn attributes(&self) -> &HashMap<String, String> {
@@ -71,6 +135,297 @@ impl HttpRequest {
     pub fn attributes_mut(&mut self) -> &mut HashMap<String, String> {
         &mut self.attributes
     }
+
+    /// Shared state of type `T` registered with `ServerBuilder::state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state.get::<T>()
+    }
+
+    pub(crate) fn set_state(&mut self, state: Arc<StateMap>) {
+        self.state = state;
+    }
+
+    /// Address of the client, `None` if the request was not read from a TCP connection.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.conn.remote_addr
+    }
+
+    /// Address of the server the client connected to.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.conn.local_addr
+    }
+
+    /// The TLS session the request was received on, `None` for cleartext connections.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.conn.tls.as_ref()
+    }
+
+    pub(crate) fn set_connection_info(&mut self, conn: Arc<ConnectionInfo>) {
+        self.conn = conn;
+    }
+
+    /// Maximum number of bytes the body accessors will read.
+    pub fn body_limit(&self) -> usize {
+        self.body_limit
+    }
+
+    /// Overrides the body limit for this request, e.g. from a filter guarding an upload route.
+    pub fn set_body_limit(&mut self, limit: usize) {
+        self.body_limit = limit;
+    }
+
+    /// Reads the whole request body.
+    ///
+    /// The body is buffered on the first call, so it can be read again by any
+    /// of the other accessors. Fails with `413` when the body is larger than
+    /// [`body_limit`](Self::body_limit) and with `400` when it cannot be read.
+    pub async fn bytes(&mut self) -> Result<Bytes, ReturnError<String>> {
+        let body = match std::mem::replace(self.inner.body_mut(), RequestBody::Consumed) {
+            RequestBody::Incoming(body) => body,
+            RequestBody::Buffered(bytes) => {
+                *self.inner.body_mut() = RequestBody::Buffered(bytes.clone());
+                return Ok(bytes);
+            }
+            RequestBody::Consumed => {
+                return Err(ReturnError::new(500, "request body has already been consumed".to_string()));
+            }
+        };
+
+        let content_length = self.headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if matches!(content_length, Some(len) if len > self.body_limit as u64) {
+            return Err(payload_too_large(self.body_limit));
+        }
+
+        let bytes = match Limited::new(body, self.body_limit).collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                if err.downcast_ref::<LengthLimitError>().is_some() {
+                    return Err(payload_too_large(self.body_limit));
+                }
+                return Err(ReturnError::new(400, format!("failed to read request body: {}", err)));
+            }
+        };
+        *self.inner.body_mut() = RequestBody::Buffered(bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Whether the client waits for `100 Continue` before sending the body.
+    /// The interim response is sent when the body is first read, so a handler
+    /// that rejects the request without reading it spares the upload.
+    pub fn expects_continue(&self) -> bool {
+        self.headers()
+            .get(hyper::header::EXPECT)
+            .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+    }
+
+    /// Takes the request body as a stream of chunks, for uploads too large to
+    /// buffer. Chunks are only received as fast as they are consumed, and
+    /// [`body_limit`](Self::body_limit) does not apply.
+    pub fn body_stream(&mut self) -> Result<BodyStream, ReturnError<String>> {
+        match std::mem::replace(self.inner.body_mut(), RequestBody::Consumed) {
+            RequestBody::Consumed => Err(ReturnError::new(500, "request body has already been consumed".to_string())),
+            body => Ok(BodyStream { body }),
+        }
+    }
+
+    /// Streams the request body into `writer` and flushes it, returning the
+    /// number of bytes written. Fails with `400` when the body can not be
+    /// read and with `500` when `writer` fails.
+    pub async fn pipe_to<W>(&mut self, writer: &mut W) -> Result<u64, ReturnError<String>>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut body = self.body_stream()?;
+        let mut written = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|err| ReturnError::new(400, format!("failed to read request body: {}", err)))?;
+            writer.write_all(&chunk).await
+                .map_err(|err| ReturnError::new(500, format!("failed to write request body: {}", err)))?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await
+            .map_err(|err| ReturnError::new(500, format!("failed to write request body: {}", err)))?;
+        Ok(written)
+    }
+
+    /// Takes the request body as a `multipart/form-data` stream of parts. The
+    /// whole body is limited to [`body_limit`](Self::body_limit), see
+    /// [`multipart_with_limits`](Self::multipart_with_limits) to change it.
+    /// Fails with `415` when the request is not `multipart/form-data`.
+    pub fn multipart(&mut self) -> Result<Multipart, ReturnError<String>> {
+        let limits = MultipartLimits::new().total(self.body_limit as u64);
+        self.multipart_with_limits(limits)
+    }
+
+    /// Like [`multipart`](Self::multipart), with the given size limits instead.
+    pub fn multipart_with_limits(&mut self, limits: MultipartLimits) -> Result<Multipart, ReturnError<String>> {
+        Multipart::new(self, limits)
+    }
+
+    /// Reads the request body as UTF-8 text.
+    pub async fn text(&mut self) -> Result<String, ReturnError<String>> {
+        let bytes = self.bytes().await?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| ReturnError::new(400, "request body is not valid UTF-8".to_string()))
+    }
+
+    /// Deserializes the request body from JSON.
+    pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, ReturnError<String>> {
+        let bytes = self.bytes().await?;
+        serde_json::from_slice(&bytes)
+            .map_err(|err| ReturnError::new(400, format!("invalid JSON body: {}", err)))
+    }
+
+    /// Deserializes an `application/x-www-form-urlencoded` request body.
+    pub async fn form<T: DeserializeOwned>(&mut self) -> Result<T, ReturnError<String>> {
+        let bytes = self.bytes().await?;
+        serde_urlencoded::from_bytes(&bytes)
+            .map_err(|err| ReturnError::new(400, format!("invalid form body: {}", err)))
+    }
+}
+
+/// The request body as a stream of chunks, see [`HttpRequest::body_stream`].
+/// Trailers are skipped. Read errors are reported as `io::Error`, so the
+/// stream can be turned into an `AsyncRead` with `tokio_util::io::StreamReader`.
+pub struct BodyStream {
+    body: RequestBody,
+}
+
+impl Stream for BodyStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match &mut self.body {
+            RequestBody::Incoming(body) => loop {
+                let frame = match futures_util::ready!(hyper::body::Body::poll_frame(Pin::new(&mut *body), cx)) {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(err)) => return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, err)))),
+                    None => return Poll::Ready(None),
+                };
+                if let Ok(data) = frame.into_data() {
+                    return Poll::Ready(Some(Ok(data)));
+                }
+            },
+            RequestBody::Buffered(_) => match std::mem::replace(&mut self.body, RequestBody::Consumed) {
+                RequestBody::Buffered(bytes) => Poll::Ready(Some(Ok(bytes))),
+                _ => unreachable!(),
+            },
+            RequestBody::Consumed => Poll::Ready(None),
+        }
+    }
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyStream").finish_non_exhaustive()
+    }
+}
+
+/// Query parameters parsed as `application/x-www-form-urlencoded`, with `+`
+/// and `%XX` decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn parse(query: &str) -> Self {
+        Self {
+            pairs: form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+        }
+    }
+
+    /// First value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `key`, e.g. `["a", "b"]` for `?tag=a&tag=b`.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs.iter().filter(|(k, _)| k == key).map(|(_, v)| v.as_str()).collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Details of the connection a request was received on, shared by all its requests.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) tls: Option<TlsInfo>,
+}
+
+/// The negotiated parameters of a TLS session.
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    pub(crate) version: String,
+    pub(crate) cipher: Option<String>,
+    pub(crate) server_name: Option<String>,
+    pub(crate) alpn_protocol: Option<String>,
+    pub(crate) peer_certificates: Vec<Vec<u8>>,
+    pub(crate) peer_subject: Option<String>,
+    pub(crate) peer_subject_alt_names: Vec<String>,
+}
+
+impl TlsInfo {
+    /// Protocol version, e.g. `TLSv1.3`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Cipher suite name, e.g. `TLS_AES_128_GCM_SHA256`.
+    pub fn cipher(&self) -> Option<&str> {
+        self.cipher.as_deref()
+    }
+
+    /// Host name the client asked for through SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Protocol selected through ALPN, e.g. `h2`.
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// DER encoded certificate chain presented by the client, its own
+    /// certificate first. Empty unless client certificates are requested.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Subject of the verified client certificate, e.g. `O=Example, CN=billing`.
+    pub fn peer_subject(&self) -> Option<&str> {
+        self.peer_subject.as_deref()
+    }
+
+    /// Subject alternative names of the verified client certificate, prefixed
+    /// with their kind: `DNS:`, `URI:`, `email:` or `IP:`.
+    pub fn peer_subject_alt_names(&self) -> &[String] {
+        &self.peer_subject_alt_names
+    }
+}
+
+fn payload_too_large(limit: usize) -> ReturnError<String> {
+    ReturnError::new(413, format!("request body exceeds the limit of {} bytes", limit))
 }
 
 impl Debug for HttpRequest {
@@ -78,10 +433,61 @@ impl Debug for HttpRequest {
         f.debug_struct("HttpRequest")
             .field("method", &self.method())
             .field("path", &self.path())
+            .field("remote_addr", &self.remote_addr())
             .field("headers", &self.headers())
             .field("path_params", &self.path_params())
             .field("url_params", &self.url_params())
             .field("attributes", &self.attributes())
             .finish()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_params_decode_and_keep_every_value() {
+        let params = QueryParams::parse("tag=a&name=J%C3%BCrgen+M&tag=b&empty=&flag");
+        assert_eq!(params.len(), 5);
+        assert!(!params.is_empty());
+        assert_eq!(params.get("name"), Some("Jürgen M"));
+        assert_eq!(params.get("tag"), Some("a"));
+        assert_eq!(params.get_all("tag"), ["a", "b"]);
+        assert_eq!(params.get("empty"), Some(""));
+        assert_eq!(params.get("flag"), Some(""));
+        assert!(params.contains_key("flag"));
+        assert!(!params.contains_key("missing"));
+        assert_eq!(params.get("missing"), None);
+        assert!(params.get_all("missing").is_empty());
+        assert_eq!(
+            params.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            ["tag", "name", "tag", "empty", "flag"],
+        );
+    }
+
+    #[test]
+    fn empty_query_has_no_params() {
+        let params = QueryParams::parse("");
+        assert!(params.is_empty());
+        assert_eq!(params.len(), 0);
+        assert_eq!(params, QueryParams::default());
+    }
+
+    #[test]
+    fn url_params_keep_the_last_value_of_a_repeated_key() {
+        let req = HttpRequest {
+            inner: hyper::Request::get("/search?tag=a&q=x%20y&tag=b").body(RequestBody::Consumed).unwrap(),
+            path_params: HashMap::new(),
+            path_param_list: Vec::new(),
+            attributes: HashMap::new(),
+            body_limit: DEFAULT_BODY_LIMIT,
+            state: Arc::default(),
+            conn: Arc::default(),
+        };
+        let params = req.url_params();
+        assert_eq!(params.get("tag").map(String::as_str), Some("b"));
+        assert_eq!(params.get("q").map(String::as_str), Some("x y"));
+        assert_eq!(req.query_params().get_all("tag"), ["a", "b"]);
+    }
+}
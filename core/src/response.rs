@@ -1 +1,166 @@
-pub type Response<T> = hyper::Response<T>;
\ No newline at end of file
+//! Response bodies.
+use std::{
+    fmt::Debug,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use hyper::body::{Frame, SizeHint};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+
+use crate::support::idle::Busy;
+
+pub type Response<T = Body> = hyper::Response<T>;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type BoxStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>;
+
+/// Size of the chunks a file is read in.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The body of a response: a buffer sent at once, or chunks produced by a
+/// stream or read from a file while the response is written.
+///
+/// A body without a known length is sent with `Transfer-Encoding: chunked`
+/// on HTTP/1.1, or as DATA frames on HTTP/2.
+pub struct Body {
+    kind: Kind,
+    /// Keeps the request counted as in flight until the body is sent.
+    busy: Option<Busy>,
+}
+
+enum Kind {
+    Full(Option<Bytes>),
+    Stream {
+        stream: BoxStream,
+        len: Option<u64>,
+    },
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Self { kind: Kind::Full(None), busy: None }
+    }
+
+    /// Sends every chunk of `stream` as soon as it is produced.
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        Self::try_from_stream(stream.map(Ok::<_, BoxError>))
+    }
+
+    /// Like [`from_stream`](Self::from_stream), an error aborts the response:
+    /// the connection is closed (HTTP/1) or the stream reset (HTTP/2), so the
+    /// client can tell the body is incomplete.
+    pub fn try_from_stream<S, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        Self {
+            kind: Kind::Stream {
+                stream: Box::pin(stream.map_err(Into::into)),
+                len: None,
+            },
+            busy: None,
+        }
+    }
+
+    /// Reads the rest of `file`, from its current position, while the
+    /// response is written. The length is known, so `Content-Length` is set.
+    pub async fn file(mut file: tokio::fs::File) -> io::Result<Self> {
+        let len = file.metadata().await?.len();
+        let position = tokio::io::AsyncSeekExt::stream_position(&mut file).await?;
+        Ok(Self::file_range(file, len.saturating_sub(position)))
+    }
+
+    /// Reads `len` bytes of `file`, from its current position.
+    pub(crate) fn file_range(file: tokio::fs::File, len: u64) -> Self {
+        let stream = ReaderStream::with_capacity(file.take(len), FILE_CHUNK_SIZE);
+        Self {
+            kind: Kind::Stream {
+                stream: Box::pin(stream.map_err(Into::into)),
+                len: Some(len),
+            },
+            busy: None,
+        }
+    }
+
+    /// Counts the request as in flight until this body is sent or dropped.
+    pub(crate) fn hold(mut self, busy: Busy) -> Self {
+        self.busy = Some(busy);
+        self
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            Kind::Full(bytes) => f.debug_tuple("Body").field(bytes).finish(),
+            Kind::Stream { len, .. } => f.debug_struct("Body").field("len", len).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Self { kind: Kind::Full(Some(bytes)), busy: None }
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Bytes::from(s).into()
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(s: &'static str) -> Self {
+        Bytes::from(s).into()
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(v: Vec<u8>) -> Self {
+        Bytes::from(v).into()
+    }
+}
+
+impl hyper::body::Body for Body {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match &mut self.kind {
+            Kind::Full(bytes) => Poll::Ready(bytes.take().filter(|bytes| !bytes.is_empty()).map(|bytes| Ok(Frame::data(bytes)))),
+            Kind::Stream { stream, .. } => stream.as_mut().poll_next(cx).map_ok(Frame::data),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.kind, Kind::Full(None))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.kind {
+            Kind::Full(bytes) => SizeHint::with_exact(bytes.as_ref().map_or(0, |bytes| bytes.len() as u64)),
+            Kind::Stream { len: Some(len), .. } => SizeHint::with_exact(*len),
+            Kind::Stream { len: None, .. } => SizeHint::default(),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::{collections::{BTreeSet, HashMap}, sync::Arc};
+
+use crate::{decode_segment, split_path, RouteInfo, Segment};
+
+/// Method of a route registered with `#[route(ANY, ...)]`, used when no route
+/// is registered for the request method itself.
+pub(crate) const ANY_METHOD: &str = "ANY";
+
+/// Prefix tree over path segments, compiled once from the `ROUTE_TABLE` when
+/// the server is built. A lookup walks the request path segment by segment,
+/// trying literal children first, then constrained parameters, plain
+/// parameters and finally a catch-all.
+#[derive(Default)]
+pub(crate) struct Router {
+    root: Node,
+}
+
+#[derive(Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    params: Vec<ParamChild>,
+    catch_all: Option<Box<CatchAllChild>>,
+    routes: HashMap<String, Arc<RouteInfo>>,
+}
+
+struct ParamChild {
+    name: String,
+    constraint: Option<regex::Regex>,
+    node: Node,
+}
+
+struct CatchAllChild {
+    name: String,
+    node: Node,
+}
+
+pub(crate) enum Match<'a> {
+    Route {
+        route: &'a Arc<RouteInfo>,
+        params: Vec<(String, String)>,
+    },
+    /// The path is registered, but not for the request method.
+    MethodNotAllowed {
+        /// Value of the `Allow` header, merged over every route matching the path.
+        allow: String,
+    },
+}
+
+impl Router {
+    pub(crate) fn new(routes: &[Arc<RouteInfo>]) -> Result<Self, String> {
+        let mut router = Router::default();
+        for route in routes {
+            router.insert(route.clone())?;
+        }
+        Ok(router)
+    }
+
+    fn insert(&mut self, route: Arc<RouteInfo>) -> Result<(), String> {
+        let pattern = route.path().to_string();
+        let mut node = &mut self.root;
+        for segment in route.path().segments()? {
+            node = match segment {
+                Segment::Static(s) => node.statics.entry(s).or_default(),
+                Segment::Param { name, constraint } => {
+                    let source = constraint.as_ref().map(|reg| reg.as_str().to_string());
+                    let position = node.params.iter().position(|param| {
+                        param.constraint.as_ref().map(|reg| reg.as_str()) == source.as_deref()
+                    });
+                    let index = match position {
+                        Some(index) => index,
+                        None => {
+                            // constrained parameters are tried before the unconstrained one
+                            let index = match constraint {
+                                Some(_) => node.params.iter().take_while(|param| param.constraint.is_some()).count(),
+                                None => node.params.len(),
+                            };
+                            node.params.insert(index, ParamChild {
+                                name: name.clone(),
+                                constraint,
+                                node: Node::default(),
+                            });
+                            index
+                        }
+                    };
+                    let param = &mut node.params[index];
+                    if param.name != name {
+                        return Err(format!(
+                            "route conflict: parameter `:{}` in `{}` clashes with `:{}` registered at the same position",
+                            name, pattern, param.name,
+                        ));
+                    }
+                    &mut param.node
+                }
+                Segment::CatchAll(name) => {
+                    let catch_all = node.catch_all.get_or_insert_with(|| Box::new(CatchAllChild {
+                        name: name.clone(),
+                        node: Node::default(),
+                    }));
+                    if catch_all.name != name {
+                        return Err(format!(
+                            "route conflict: catch-all `*{}` in `{}` clashes with `*{}` registered at the same position",
+                            name, pattern, catch_all.name,
+                        ));
+                    }
+                    &mut catch_all.node
+                }
+            };
+        }
+
+        let method = route.method().to_string();
+        if node.routes.contains_key(&method) {
+            return Err(format!("route conflict: `{} {}` is registered more than once", method, pattern));
+        }
+        node.routes.insert(method, route);
+        Ok(())
+    }
+
+    /// Finds the route registered for `method` and `path`, together with its
+    /// path parameters in the order they appear in the pattern. A node
+    /// matching the path without a route for the method does not end the
+    /// search, a less specific pattern may still have one.
+    pub(crate) fn find(&self, method: &str, path: &str) -> Option<Match<'_>> {
+        let decoded: Vec<_> = split_path(path).map(decode_segment).collect();
+        let segments: Vec<&str> = decoded.iter().map(|segment| segment.as_ref()).collect();
+        let mut params = Vec::new();
+        let mut candidates = Vec::new();
+        if let Some(route) = self.root.find(method, &segments, &mut params, &mut candidates) {
+            return Some(Match::Route { route, params });
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Match::MethodNotAllowed {
+            allow: allow_header(candidates.iter().flat_map(|node| node.routes.keys())),
+        })
+    }
+}
+
+impl Node {
+    /// Walks the tree in order of precedence. Nodes matching the whole path
+    /// but without a route for `method` are collected into `candidates`.
+    fn find<'a>(
+        &'a self,
+        method: &str,
+        segments: &[&str],
+        params: &mut Vec<(String, String)>,
+        candidates: &mut Vec<&'a Node>,
+    ) -> Option<&'a Arc<RouteInfo>> {
+        if let Some((segment, rest)) = segments.split_first() {
+            if let Some(found) = self.statics.get(*segment).and_then(|child| child.find(method, rest, params, candidates)) {
+                return Some(found);
+            }
+
+            if !segment.is_empty() {
+                for param in &self.params {
+                    if param.constraint.as_ref().is_some_and(|reg| !reg.is_match(segment)) {
+                        continue;
+                    }
+                    params.push((param.name.clone(), segment.to_string()));
+                    if let Some(found) = param.node.find(method, rest, params, candidates) {
+                        return Some(found);
+                    }
+                    params.pop();
+                }
+            }
+        } else if let Some(found) = self.route(method, candidates) {
+            return Some(found);
+        }
+
+        let catch_all = self.catch_all.as_ref()?;
+        params.push((catch_all.name.clone(), segments.join("/")));
+        match catch_all.node.route(method, candidates) {
+            Some(found) => Some(found),
+            None => {
+                params.pop();
+                None
+            }
+        }
+    }
+
+    /// The route of this node answering `method`: `HEAD` falls back to `GET`,
+    /// and any method to `ANY`.
+    fn route<'a>(&'a self, method: &str, candidates: &mut Vec<&'a Node>) -> Option<&'a Arc<RouteInfo>> {
+        if self.routes.is_empty() {
+            return None;
+        }
+        let route = self.routes.get(method)
+            .or_else(|| if method == "HEAD" { self.routes.get("GET") } else { None })
+            .or_else(|| self.routes.get(ANY_METHOD));
+        if route.is_none() {
+            candidates.push(self);
+        }
+        route
+    }
+}
+
+/// `HEAD` is answered by the `GET` handler and `OPTIONS` by the server itself.
+/// `ANY` never shows up here, a node with such a route answers every method.
+fn allow_header<'a>(registered: impl Iterator<Item = &'a String>) -> String {
+    let mut methods: BTreeSet<&str> = registered.map(String::as_str).collect();
+    if methods.contains("GET") {
+        methods.insert("HEAD");
+    }
+    methods.insert("OPTIONS");
+    methods.into_iter().collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Path;
+
+    fn route(method: &str, path: &str) -> Arc<RouteInfo> {
+        Arc::new(RouteInfo::new(
+            method.to_string(),
+            Path::new(path),
+            Box::new(|_| Box::pin(async { unreachable!() })),
+        ))
+    }
+
+    fn router(routes: &[(&str, &str)]) -> Router {
+        let routes: Vec<_> = routes.iter().map(|(method, path)| route(method, path)).collect();
+        Router::new(&routes).unwrap()
+    }
+
+    /// The pattern of the matched route and its parameters, or the `Allow` header.
+    fn find(router: &Router, method: &str, path: &str) -> Option<Result<(String, Vec<(String, String)>), String>> {
+        router.find(method, path).map(|found| match found {
+            Match::Route { route, params } => Ok((route.path().to_string(), params)),
+            Match::MethodNotAllowed { allow } => Err(allow),
+        })
+    }
+
+    fn pattern(router: &Router, method: &str, path: &str) -> Option<String> {
+        match find(router, method, path) {
+            Some(Ok((pattern, _))) => Some(pattern),
+            _ => None,
+        }
+    }
+
+    fn param(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn static_before_constrained_before_param_before_catch_all() {
+        let router = router(&[
+            ("GET", "/users/*rest"),
+            ("GET", "/users/:name"),
+            ("GET", "/users/:id(\\d+)"),
+            ("GET", "/users/me"),
+        ]);
+        assert_eq!(pattern(&router, "GET", "/users/me").as_deref(), Some("/users/me"));
+        assert_eq!(pattern(&router, "GET", "/users/12").as_deref(), Some("/users/:id(\\d+)"));
+        assert_eq!(pattern(&router, "GET", "/users/bob").as_deref(), Some("/users/:name"));
+        assert_eq!(pattern(&router, "GET", "/users/bob/posts").as_deref(), Some("/users/*rest"));
+        assert_eq!(pattern(&router, "GET", "/users").as_deref(), Some("/users/*rest"));
+    }
+
+    #[test]
+    fn params_in_pattern_order() {
+        let router = router(&[("GET", "/users/:id/posts/:post"), ("GET", "/files/*path")]);
+        assert_eq!(
+            find(&router, "GET", "/users/1/posts/2"),
+            Some(Ok(("/users/:id/posts/:post".to_string(), vec![param("id", "1"), param("post", "2")]))),
+        );
+        assert_eq!(
+            find(&router, "GET", "/files/a/b.txt"),
+            Some(Ok(("/files/*path".to_string(), vec![param("path", "a/b.txt")]))),
+        );
+        assert_eq!(find(&router, "GET", "/users/1/posts"), None);
+        assert_eq!(find(&router, "GET", "/users//posts/2"), None);
+    }
+
+    #[test]
+    fn trailing_slash_is_ignored() {
+        let router = router(&[("GET", "/users/"), ("GET", "/")]);
+        assert_eq!(pattern(&router, "GET", "/users").as_deref(), Some("/users/"));
+        assert_eq!(pattern(&router, "GET", "/users/").as_deref(), Some("/users/"));
+        assert_eq!(pattern(&router, "GET", "/").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn backtracks_to_a_route_for_the_method() {
+        let router = router(&[
+            ("GET", "/users/:id(\\d+)"),
+            ("POST", "/users/:name"),
+            ("GET", "/users/:name/avatar"),
+            ("PUT", "/users/*rest"),
+        ]);
+        assert_eq!(
+            find(&router, "POST", "/users/12"),
+            Some(Ok(("/users/:name".to_string(), vec![param("name", "12")]))),
+        );
+        assert_eq!(pattern(&router, "GET", "/users/12").as_deref(), Some("/users/:id(\\d+)"));
+        assert_eq!(
+            find(&router, "PUT", "/users/12/avatar"),
+            Some(Ok(("/users/*rest".to_string(), vec![param("rest", "12/avatar")]))),
+        );
+    }
+
+    #[test]
+    fn head_falls_back_to_get_and_any_to_every_method() {
+        let router = router(&[("GET", "/a"), ("ANY", "/b"), ("GET", "/c"), ("ANY", "/c")]);
+        assert_eq!(pattern(&router, "HEAD", "/a").as_deref(), Some("/a"));
+        assert_eq!(pattern(&router, "DELETE", "/b").as_deref(), Some("/b"));
+        let method = |method| match router.find(method, "/c") {
+            Some(Match::Route { route, .. }) => route.method().to_string(),
+            _ => panic!("no route for {} /c", method),
+        };
+        assert_eq!(method("GET"), "GET");
+        assert_eq!(method("HEAD"), "GET");
+        assert_eq!(method("POST"), "ANY");
+    }
+
+    #[test]
+    fn allow_is_merged_over_matching_routes() {
+        let router = router(&[
+            ("GET", "/users/:id(\\d+)"),
+            ("POST", "/users/:name"),
+            ("DELETE", "/users/*rest"),
+            ("PUT", "/users/me"),
+        ]);
+        assert_eq!(
+            find(&router, "PATCH", "/users/12"),
+            Some(Err("DELETE, GET, HEAD, OPTIONS, POST".to_string())),
+        );
+        assert_eq!(find(&router, "PATCH", "/users/me"), Some(Err("DELETE, OPTIONS, POST, PUT".to_string())));
+        assert_eq!(find(&router, "GET", "/posts"), None);
+    }
+
+    #[test]
+    fn conflicting_routes_are_rejected() {
+        let conflict = |routes: &[(&str, &str)]| {
+            let routes: Vec<_> = routes.iter().map(|(method, path)| route(method, path)).collect();
+            Router::new(&routes).err().unwrap()
+        };
+        let err = conflict(&[("GET", "/users/:id"), ("POST", "/users/:name")]);
+        assert!(err.contains("parameter `:name` in `/users/:name` clashes with `:id`"), "{}", err);
+        let err = conflict(&[("GET", "/files/*path"), ("POST", "/files/*rest")]);
+        assert!(err.contains("catch-all `*rest` in `/files/*rest` clashes with `*path`"), "{}", err);
+        let err = conflict(&[("GET", "/users/:id"), ("GET", "/users/:id/")]);
+        assert!(err.contains("`GET /users/:id/` is registered more than once"), "{}", err);
+        let err = conflict(&[("GET", "/files/*path/raw")]);
+        assert!(err.contains("must be the last segment"), "{}", err);
+    }
+}
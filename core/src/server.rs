@@ -1,145 +1,281 @@
-use std::{pin::Pin, future::Future, net::SocketAddr};
+use std::{pin::Pin, future::Future, net::SocketAddr, sync::{atomic::Ordering, Arc}, time::{Duration, Instant}};
 
-use boring::ssl::{SslAcceptor, SslMethod};
 use bytes::Bytes;
 
-use http_body_util::Full;
+use tracing::Instrument;
+use futures_util::StreamExt;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite}, net::TcpStream, sync::{watch, OwnedSemaphorePermit, Semaphore}, task::JoinSet};
+use crate::{ROUTE_TABLE, access_log::{AccessEntry, AccessLog}, listener::{incoming, Accepted, Bind, Listener}, router::{Match, Router}, state::StateMap, static_files::StaticDir, support::{idle::{Idle, InFlight}, rewind::Rewind, tokiort::{TokioIo, TokioExecutor, TokioTimer}}, request::{HttpRequest, Body, ConnectionInfo, Method, DEFAULT_BODY_LIMIT}, response::{Body as ResponseBody, Response}, tls::{reload_tls, CertFiles, TlsAcceptor, TlsConfig, TlsMetrics}};
 
-use tokio::{net::TcpListener, runtime::Handle};
-use regex::Regex;
-use crate::{ROUTE_TABLE, support::tokiort::{TokioIo, TokioExecutor}, request::{HttpRequest, Body}, Predicate, response::Response};
+pub use crate::tls::{ClientAuth, SslFiletype};
 
-#[derive(Clone, Copy)]
-pub struct Service;
+#[derive(Clone)]
+pub struct Service {
+    max_body_size: usize,
+    router: Arc<Router>,
+    state: Arc<StateMap>,
+    access_log: Option<AccessLog>,
+    conn: Arc<ConnectionInfo>,
+    requests: Option<InFlight>,
+}
 
 impl hyper::service::Service<hyper::Request<Body>> for Service {
-    type Response = hyper::Response<Full<Bytes>>;
+    type Response = hyper::Response<ResponseBody>;
     type Error = hyper::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&self, req: hyper::Request<Body>) -> Self::Future {
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            peer = tracing::field::Empty,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+        if let Some(remote_addr) = self.conn.remote_addr {
+            span.record("peer", tracing::field::display(remote_addr));
+        }
+        let access_entry = self.access_log.as_ref().map(|_| AccessEntry::new(&req, self.conn.remote_addr));
+        let access_log = self.access_log.clone();
 
-        println!("{}, {}", req.uri().path().to_string(), req.method().as_str().to_string());
         // reform the request
         let mut new_req = HttpRequest::new(req);
+        new_req.set_body_limit(self.max_body_size);
+        new_req.set_state(self.state.clone());
+        new_req.set_connection_info(self.conn.clone());
+        let busy = self.requests.as_ref().map(InFlight::start);
+        let dispatched = handle(new_req, self.router.clone());
 
-
-        // get the filter table
-        let filter_table = crate::FILTER_TABLE.read().unwrap();
-        for filter_info in filter_table.iter() {
-            let path = new_req.path().to_string();
-            let _method = new_req.method().as_str().to_string();
-            let predicate = filter_info.predicate();
-            let is_matched = match predicate {
-                Predicate::PathPattern(pattern) => {
-                    let reg_result = Regex::new(&pattern);
-                    if reg_result.is_err() {
-                        false
-                    } else {
-                        let reg = reg_result.unwrap();
-                        reg.is_match(&path)
+        Box::pin(async move {
+            let start = Instant::now();
+            let mut result = dispatched.await;
+            if let (Ok(res), Some(busy)) = (&mut result, busy) {
+                let body = std::mem::take(res.body_mut());
+                *res.body_mut() = body.hold(busy);
+            }
+            let latency = start.elapsed();
+            match &result {
+                Ok(res) => {
+                    let status = res.status().as_u16();
+                    let span = tracing::Span::current();
+                    span.record("status", status);
+                    span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+                    tracing::info!("request completed");
+                    if let (Some(access_log), Some(access_entry)) = (access_log, access_entry) {
+                        let bytes = hyper::body::Body::size_hint(res.body()).exact();
+                        access_log.write(&access_entry, status, bytes, latency);
                     }
                 }
-
-            };
-
-            if !is_matched {
-                continue;
+                Err(err) => tracing::error!(error = %err, "request failed"),
             }
+            result
+        }.instrument(span))
+    }
+}
 
-            let handler = filter_info.handler();
-            // let mut new_req = arc_new_req.clone();
-            let chain = handler(new_req);
-
-            let chain = tokio::task::block_in_place(move|| {
-                Handle::current().block_on(chain)
-            });
-            // let rt = Runtime::new().unwrap();
-            // let chain = rt.block_on(chain);
-            match chain {
-                crate::Chain::Continune(req) => {
-                    new_req = req;
-                }
-                crate::Chain::Reject(err) => {
-                    let mut builder = Response::builder();
-                    for (k, v) in err.headers.iter() {
-                        builder.headers_mut().unwrap().insert(k, v.clone());
-                    }
-                    let res = builder
-                        .status(err.response_code)
-                        .body(Full::new(Bytes::from(err.response_body.to_string()))).unwrap();
-                    return Box::pin(async{Ok(res)});
+/// Runs the filters and the route handler inside one future, so nothing here
+/// blocks the runtime and the table locks are never held across an await.
+async fn handle(mut new_req: HttpRequest, router: Arc<Router>) -> Result<hyper::Response<ResponseBody>, hyper::Error> {
+    let mut next_filter = 0;
+    loop {
+        let chain = {
+            let filter_table = crate::FILTER_TABLE.read().unwrap();
+            let path = crate::decode_path(new_req.path());
+            let matched = filter_table.iter()
+                .enumerate()
+                .skip(next_filter)
+                .find(|(_, filter_info)| filter_info.predicate().is_match(&path))
+                .map(|(index, filter_info)| (index, filter_info.handler()));
+            match matched {
+                Some((index, handler)) => {
+                    next_filter = index + 1;
+                    handler(new_req)
                 }
+                None => break,
             }
-        }
+        };
 
-        let path = new_req.path().to_string();
-        let method = new_req.method().as_str().to_string();
+        match chain.await {
+            crate::Chain::Continune(req) => {
+                new_req = req;
+            }
+            crate::Chain::Reject(err) => {
+                return Ok(err.into_response());
+            }
+        }
+    }
 
-        // check if the request path and method is exist in route table
-        let router_table = ROUTE_TABLE.read().unwrap();
-        let route_info = router_table.iter().find(|route| {
-            route.method() == method && route.path().pattern_match(&path).is_some()
-        }).map(|route| {
-            route
-        });
+    // check if the request path and method is exist in the router
+    let found = router.find(new_req.method().as_str(), new_req.path());
+    match found {
         // if the service exists, call the service function
-        let res = if let Some(r) = route_info.clone() {
-            let handler = r.handler();
-            let path_params = r.path().pattern_match(&path);
-            if path_params.is_some() {
-                new_req.set_path_params(path_params.unwrap());
+        Some(Match::Route { route, params }) => {
+            // HEAD may be answered by the GET handler, the body is stripped below
+            let is_head = new_req.method() == Method::HEAD;
+            new_req.set_path_param_list(params);
+            let res = route.handler()(new_req).await?;
+            if is_head {
+                Ok(strip_body(res))
+            } else {
+                Ok(res)
             }
-
-            handler(new_req)
-        } else {
-            // if the service not exists, return 404
-            Box::pin(async{Ok(hyper::Response::builder().status(hyper::StatusCode::NOT_FOUND).body(Full::new(Bytes::from("not found!"))).unwrap())})
-            
-        };
-
-        res
-
+        }
+        // the path exists but not for this method
+        Some(Match::MethodNotAllowed { allow }) => {
+            let status = if new_req.method() == Method::OPTIONS {
+                hyper::StatusCode::NO_CONTENT
+            } else {
+                hyper::StatusCode::METHOD_NOT_ALLOWED
+            };
+            Ok(Response::builder()
+                .status(status)
+                .header(hyper::header::ALLOW, allow)
+                .body(ResponseBody::empty())
+                .unwrap())
+        }
+        // if the service not exists, return 404
+        None => Ok(hyper::Response::builder().status(hyper::StatusCode::NOT_FOUND).body(ResponseBody::from("not found!")).unwrap()),
     }
 }
 
-pub enum SslFiletype {
-    PEM,
-    ASN1,
-}
-
-impl SslFiletype {
-    fn to_boring(&self) -> boring::ssl::SslFiletype {
-        match self {
-            SslFiletype::PEM => boring::ssl::SslFiletype::PEM,
-            SslFiletype::ASN1 => boring::ssl::SslFiletype::ASN1,
+/// Drops the body of a `GET` response sent for a `HEAD` request, keeping its length.
+fn strip_body(res: hyper::Response<ResponseBody>) -> hyper::Response<ResponseBody> {
+    let (mut parts, body) = res.into_parts();
+    if !parts.headers.contains_key(hyper::header::CONTENT_LENGTH) {
+        if let Some(len) = hyper::body::Body::size_hint(&body).exact() {
+            parts.headers.insert(hyper::header::CONTENT_LENGTH, len.into());
         }
     }
+    hyper::Response::from_parts(parts, ResponseBody::empty())
 }
 
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after failing to accept, e.g. when out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+/// Default of `ServerBuilder::header_read_timeout`, the same as hyper's.
+const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct ServerBuilder {
-    addr: SocketAddr,
+    binds: Vec<Bind>,
     protocol: Protocol,
-    ssl_acceptor: Option<SslAcceptor>,
+    tls: Option<CertFiles>,
+    sni: Vec<(String, CertFiles)>,
+    client_auth: Option<ClientAuth>,
+    tls_reload_interval: Option<Duration>,
+    tls_reload_on_sighup: bool,
+    tls_handshake_timeout: Duration,
+    max_body_size: usize,
+    state: StateMap,
+    shutdown_timeout: Duration,
+    access_log: Option<AccessLog>,
+    max_connections: Option<usize>,
+    limits: ConnectionLimits,
+    static_dirs: Vec<(String, StaticDir)>,
 }
 
 impl ServerBuilder {
-    fn new(addr: SocketAddr) -> Self {
+    fn new(bind: Bind) -> Self {
         Self {
-            addr,
+            binds: vec![bind],
             protocol: Protocol::HTTP1,
-            ssl_acceptor: None,
+            tls: None,
+            sni: Vec::new(),
+            client_auth: None,
+            tls_reload_interval: None,
+            tls_reload_on_sighup: false,
+            tls_handshake_timeout: DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            max_body_size: DEFAULT_BODY_LIMIT,
+            state: StateMap::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            access_log: None,
+            max_connections: None,
+            limits: ConnectionLimits::default(),
+            static_dirs: Vec::new(),
         }
     }
 
+    /// Also listens on `addr`.
+    pub fn bind(&mut self, addr: SocketAddr) -> &mut Self {
+        self.binds.push(Bind::Addr(addr));
+        self
+    }
+
+    /// Also accepts connections on an already bound listener, e.g. one passed
+    /// in by systemd socket activation or bound to port 0 by a test.
+    pub fn listener(&mut self, listener: std::net::TcpListener) -> &mut Self {
+        self.binds.push(Bind::Listener(listener));
+        self
+    }
+
+    /// Also listens on the Unix domain socket at `path`. A stale socket left
+    /// at `path` is replaced, and removed again when the server stops. `build`
+    /// fails when a server still listens on it or `path` is another file.
+    /// Connections on it are always cleartext, TLS only applies to TCP.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<std::path::Path>>(&mut self, path: P) -> &mut Self {
+        self.binds.push(Bind::Unix(path.as_ref().to_path_buf()));
+        self
+    }
+
     pub fn ssl(&mut self, private_key_file: &str, server_cert_file: &str, ssl_file_type: SslFiletype) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
-        acceptor.set_private_key_file(private_key_file, ssl_file_type.to_boring())?;
-        acceptor.set_certificate_chain_file(server_cert_file)?;
-        self.ssl_acceptor = Some(acceptor.build());
+        let files = CertFiles {
+            private_key_file: private_key_file.to_string(),
+            cert_file: server_cert_file.to_string(),
+            file_type: ssl_file_type,
+        };
+        // load the files now, so that a bad key or certificate is reported here
+        files.check()?;
+        self.tls = Some(files);
         Ok(self)
     }
 
+    /// Adds a certificate for clients asking for `server_name` through SNI,
+    /// e.g. `api.example.com` or `*.example.com`. Other clients get the
+    /// certificate given to [`ssl`](Self::ssl), which is required as well.
+    pub fn ssl_sni(&mut self, server_name: &str, private_key_file: &str, server_cert_file: &str, ssl_file_type: SslFiletype) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        let files = CertFiles {
+            private_key_file: private_key_file.to_string(),
+            cert_file: server_cert_file.to_string(),
+            file_type: ssl_file_type,
+        };
+        files.check()?;
+        self.sni.push((server_name.to_string(), files));
+        Ok(self)
+    }
+
+    /// Checks the key, certificate, CA and CRL files every `interval` and
+    /// reloads them when one has changed. New connections use the new
+    /// certificates, open ones are kept.
+    pub fn tls_reload_interval(&mut self, interval: Duration) -> &mut Self {
+        self.tls_reload_interval = Some(interval);
+        self
+    }
+
+    /// Reloads the TLS files whenever the process receives SIGHUP, off by
+    /// default. This installs a handler for the signal, leave it off when
+    /// the application handles SIGHUP itself. Only supported on unix.
+    pub fn tls_reload_on_sighup(&mut self, enabled: bool) -> &mut Self {
+        self.tls_reload_on_sighup = enabled;
+        self
+    }
+
+    /// Drops TLS connections that have not completed the handshake within
+    /// `timeout`, 10 seconds by default.
+    pub fn tls_handshake_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.tls_handshake_timeout = timeout;
+        self
+    }
+
+    /// Verifies client certificates on TLS connections (mutual TLS). The
+    /// verified certificate is available to filters and handlers through
+    /// `HttpRequest::tls_info`. The CA and CRL files are loaded by `build`.
+    pub fn client_auth(&mut self, client_auth: ClientAuth) -> &mut Self {
+        self.client_auth = Some(client_auth);
+        self
+    }
+
     pub fn h1(&mut self) -> &mut Self {
         self.protocol = Protocol::HTTP1;
         self
@@ -150,103 +286,530 @@ impl ServerBuilder {
         self
     }
 
+    /// Serves HTTP/1.1 and HTTP/2 on the same listener. With TLS the protocol
+    /// is negotiated through ALPN; cleartext connections starting with the
+    /// HTTP/2 preface (prior knowledge, as sent by `curl --http2-prior-knowledge`
+    /// or gRPC clients) are served as HTTP/2 and all others as HTTP/1.1.
+    pub fn auto(&mut self) -> &mut Self {
+        self.protocol = Protocol::Auto;
+        self
+    }
+
+    /// Sets the maximum request body size read by `HttpRequest::bytes` and friends.
+    pub fn max_body_size(&mut self, max_body_size: usize) -> &mut Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Registers a value shared by all handlers and filters, reachable with
+    /// `HttpRequest::state::<T>()` or the `State<T>` extractor. Registering a
+    /// second value of the same type replaces the first.
+    pub fn state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Serves the files below `dir` under the path `prefix`, e.g.
+    /// `static_dir("/assets", "./public")` answers `/assets/css/site.css` with
+    /// `./public/css/site.css`. It is registered as a `GET {prefix}/*path` route.
+    pub fn static_dir<P: Into<std::path::PathBuf>>(&mut self, prefix: &str, dir: P) -> &mut Self {
+        self.static_files(prefix, StaticDir::new(dir))
+    }
+
+    /// Like [`static_dir`](Self::static_dir), with the index file or
+    /// precompressed variants configured.
+    pub fn static_files(&mut self, prefix: &str, dir: StaticDir) -> &mut Self {
+        self.static_dirs.push((prefix.to_string(), dir));
+        self
+    }
+
+    /// Sets how long a graceful shutdown waits for open connections, 30 seconds by default.
+    pub fn shutdown_timeout(&mut self, shutdown_timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Writes an access log line for every answered request. Request spans
+    /// and connection errors are reported through `tracing` regardless.
+    pub fn access_log(&mut self, access_log: AccessLog) -> &mut Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Maximum number of open connections. Once reached, new connections
+    /// wait in the listen backlog until another one is closed.
+    pub fn max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Closes HTTP/1 connections that do not send the complete head of a
+    /// request within `timeout`, counted from when the server starts waiting
+    /// for it, including between keep-alive requests, 30 seconds by default.
+    /// With [`auto`](Self::auto) it also limits the wait for the connection
+    /// preface, for both protocols.
+    pub fn header_read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.limits.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections that have no request in flight and no traffic for
+    /// `timeout`, e.g. an HTTP/1 client idling between keep-alive requests.
+    /// A request counts as in flight until its response body has been sent,
+    /// so slow handlers and streamed responses are not cut short. HTTP/2
+    /// clients get a GOAWAY.
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.limits.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum size of the request head (HTTP/1, answered with `431`) or of
+    /// the header list (HTTP/2).
+    pub fn max_header_size(&mut self, max_header_size: usize) -> &mut Self {
+        self.limits.max_header_size = Some(max_header_size);
+        self
+    }
+
+    /// Maximum number of concurrent streams a HTTP/2 client may open on one
+    /// connection, 200 by default.
+    pub fn h2_max_concurrent_streams(&mut self, max_concurrent_streams: u32) -> &mut Self {
+        self.limits.h2_max_concurrent_streams = Some(max_concurrent_streams);
+        self
+    }
+
+    /// HTTP/2 flow control window of each stream, in bytes.
+    pub fn h2_initial_stream_window_size(&mut self, size: u32) -> &mut Self {
+        self.limits.h2_initial_stream_window_size = Some(size);
+        self
+    }
+
+    /// HTTP/2 flow control window of the whole connection, in bytes.
+    pub fn h2_initial_connection_window_size(&mut self, size: u32) -> &mut Self {
+        self.limits.h2_initial_connection_window_size = Some(size);
+        self
+    }
+
     pub async fn build(&self) -> Result<Server, Box<dyn std::error::Error>> {
-        let listener = TcpListener::bind(self.addr).await?;
+        let mut routes = ROUTE_TABLE.read().unwrap().clone();
+        routes.extend(self.static_dirs.iter().map(|(prefix, dir)| Arc::new(dir.clone().route(prefix))));
+        let router = Router::new(&routes)?;
+        let tls = match &self.tls {
+            Some(default) => Some(Arc::new(TlsAcceptor::new(TlsConfig {
+                default: default.clone(),
+                sni: self.sni.clone(),
+                alpn_protocols: self.protocol.alpn_protocols(),
+                client_auth: self.client_auth.clone(),
+            })?)),
+            None if !self.sni.is_empty() => {
+                return Err("`ssl_sni` needs a default certificate set with `ssl`".into());
+            }
+            None => None,
+        };
+        let mut listeners = Vec::with_capacity(self.binds.len());
+        for bind in &self.binds {
+            listeners.push(bind.bind().await?);
+        }
         Ok(Server { 
-            listener,  
-            service: Service, 
+            listeners,  
+            service: Service {
+                max_body_size: self.max_body_size,
+                router: Arc::new(router),
+                state: Arc::new(self.state.clone()),
+                access_log: self.access_log.clone(),
+                conn: Arc::default(),
+                requests: None,
+            },
             protocol: self.protocol,
-            ssl_acceptor: self.ssl_acceptor.clone(),
+            tls,
+            tls_reload_interval: self.tls_reload_interval,
+            tls_reload_on_sighup: self.tls_reload_on_sighup,
+            tls_handshake_timeout: self.tls_handshake_timeout,
+            shutdown_timeout: self.shutdown_timeout,
+            connections: self.max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            limits: self.limits,
         })
     }
 
 }
 
 pub struct Server {
-    listener: TcpListener,
+    listeners: Vec<Listener>,
     service: Service,
     protocol: Protocol, 
-    ssl_acceptor: Option<SslAcceptor>,
+    tls: Option<Arc<TlsAcceptor>>,
+    tls_reload_interval: Option<Duration>,
+    tls_reload_on_sighup: bool,
+    tls_handshake_timeout: Duration,
+    shutdown_timeout: Duration,
+    connections: Option<Arc<Semaphore>>,
+    limits: ConnectionLimits,
 }
 
 impl Server {
     pub fn builder(addr: SocketAddr) -> ServerBuilder {
-        ServerBuilder::new(addr)
+        ServerBuilder::new(Bind::Addr(addr))
     }
 
+    /// Starts a builder for an already bound listener instead of an address.
+    pub fn builder_from_listener(listener: std::net::TcpListener) -> ServerBuilder {
+        ServerBuilder::new(Bind::Listener(listener))
+    }
+
+    /// Starts a builder for a Unix domain socket instead of an address.
+    #[cfg(unix)]
+    pub fn builder_from_unix<P: AsRef<std::path::Path>>(path: P) -> ServerBuilder {
+        ServerBuilder::new(Bind::Unix(path.as_ref().to_path_buf()))
+    }
+
+    /// Handshake counters of the TLS listeners, `None` without `ssl`. They
+    /// keep counting once the server is started.
+    pub fn tls_metrics(&self) -> Option<Arc<TlsMetrics>> {
+        self.tls.as_ref().map(|tls| tls.metrics.clone())
+    }
+
+    /// Addresses of the TCP listeners, with the port picked by the system
+    /// when binding port 0.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(Listener::local_addr).collect()
+    }
+
+    /// Serves until SIGINT or SIGTERM is received, then shuts down gracefully,
+    /// see [`start_with_shutdown`](Self::start_with_shutdown).
     pub async fn start(self) -> Result<(), Box<dyn std::error::Error>> {
+        self.start_with_shutdown(shutdown_signal()).await
+    }
+
+    /// Serves until `signal` completes. The server then stops accepting, asks
+    /// every open connection to close once its in-flight requests are answered
+    /// (`Connection: close` on HTTP/1, GOAWAY on HTTP/2) and waits for them up
+    /// to the shutdown timeout, after which the remaining ones are aborted.
+    pub async fn start_with_shutdown<F>(self, signal: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Future<Output = ()>,
+    {
+        // every connection task holds a receiver to learn about the shutdown,
+        // and is tracked so that it can be aborted once the timeout elapsed
+        let (shutdown_tx, shutdown_rx) = watch::channel(());
+        let mut tasks = JoinSet::new();
+        tokio::pin!(signal);
+        if let Some(tls) = &self.tls {
+            if self.tls_reload_interval.is_some() || self.tls_reload_on_sighup {
+                tokio::task::spawn(reload_tls(tls.clone(), self.tls_reload_interval, self.tls_reload_on_sighup, shutdown_rx.clone()));
+            }
+        }
+
+        let mut incoming = incoming(self.listeners);
         loop {
-            let (stream, _) = self.listener.accept().await?;
-            if let Some(acceptor) = &self.ssl_acceptor {
-                // let stream = tokio_boring::accept(&acceptor, stream).await;
-                if let Ok(stream) = tokio_boring::accept(&acceptor, stream).await {
-                    let io = TokioIo::new(stream);
-                    tokio::task::spawn(async move {
-                        match self.protocol {
-                            Protocol::HTTP1 => {
-                                if let Err(err) = hyper::server::conn::http1::Builder::new()
-                                .serve_connection(
-                                    io,
-                                    self.service,
-                                ).await
-                                {
-                                    println!("Failed to serve connection: {}", err.message());
-                                }
-                            },
-                            Protocol::HTTP2 => {
-                                if let Err(err) = hyper::server::conn::http2::Builder::new(TokioExecutor)
-                                .serve_connection(
-                                    io,
-                                    self.service,
-                                ).await
-                                {
-                                    println!("Failed to serve h2 connection: {}", err.to_string());
-                                }
-                            },
-                            
-                        };
-                        
-                    });
-                } else {
-                    println!("Failed to accept ssl connection");
+            // finished connections are reaped while waiting
+            let permit = match &self.connections {
+                Some(limit) => tokio::select! {
+                    permit = limit.clone().acquire_owned() => Some(permit?),
+                    Some(_) = tasks.join_next() => continue,
+                    _ = &mut signal => break,
+                },
+                None => None,
+            };
+            let accepted = tokio::select! {
+                accepted = incoming.next() => match accepted {
+                    Some(Ok(accepted)) => accepted,
+                    Some(Err(err)) if is_connection_error(&err) => {
+                        tracing::debug!(error = %err, "failed to accept connection");
+                        continue;
+                    }
+                    Some(Err(err)) => {
+                        // e.g. too many open files, wait for some to be closed
+                        tracing::error!(error = %err, "failed to accept connection");
+                        tokio::select! {
+                            _ = tokio::time::sleep(ACCEPT_ERROR_BACKOFF) => continue,
+                            _ = &mut signal => break,
+                        }
+                    }
+                    None => break,
+                },
+                Some(_) = tasks.join_next() => continue,
+                _ = &mut signal => break,
+            };
+            let mut connection = Connection {
+                service: self.service.clone(),
+                protocol: self.protocol,
+                limits: self.limits,
+                shutdown: shutdown_rx.clone(),
+                _permit: permit,
+            };
+            let (stream, remote_addr) = match accepted {
+                Accepted::Tcp(stream, remote_addr) => (stream, remote_addr),
+                #[cfg(unix)]
+                Accepted::Unix(stream) => {
+                    tasks.spawn(connection.serve(stream));
+                    continue;
                 }
+            };
+            let conn = ConnectionInfo {
+                remote_addr: Some(remote_addr),
+                local_addr: stream.local_addr().ok(),
+                tls: None,
+            };
+            if let Some(tls) = &self.tls {
+                // the handshake runs in the connection task, so that a client
+                // stalling it does not hold up the others
+                tasks.spawn(connection.serve_tls(tls.clone(), self.tls_handshake_timeout, stream, conn));
             } else {
-                let io = TokioIo::new(stream);
-                tokio::task::spawn(async move {
-                    
-                    match self.protocol {
-                        Protocol::HTTP1 => {
-                            if let Err(err) = hyper::server::conn::http1::Builder::new()
-                            .serve_connection(
-                                io,
-                                self.service,
-                            ).await
-                            {
-                                println!("Failed to serve connection: {}", err.message());
-                            }
-                        },
-                        Protocol::HTTP2 => {
-                            if let Err(err) = hyper::server::conn::http2::Builder::new(TokioExecutor)
-                            .serve_connection(
-                                io,
-                                self.service,
-                            ).await
-                            {
-                                println!("Failed to serve h2 connection: {}", err.message());
-                            }
-                        },
-                        
-                    };
-                    
-                });
+                connection.service.conn = Arc::new(conn);
+                tasks.spawn(connection.serve(stream));
             };
-            
         }
+
+        drop(incoming);
+        let _ = shutdown_tx.send(());
+        let closed = tokio::time::timeout(self.shutdown_timeout, async {
+            while tasks.join_next().await.is_some() {}
+        });
+        if closed.await.is_err() {
+            tracing::warn!(connections = tasks.len(), "shutdown timeout elapsed, aborting the remaining connections");
+            tasks.shutdown().await;
+        }
+        Ok(())
     }
 }
 
+/// Errors of a single connection, which was reset before it was accepted.
+fn is_connection_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Completes on SIGINT (Ctrl-C) or, on unix, SIGTERM.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            }
+            Err(err) => tracing::error!(error = %err, "failed to listen for SIGTERM"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::error!(error = %err, "failed to listen for SIGINT");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Settings applied to every connection, see the `ServerBuilder` methods of
+/// the same names.
+#[derive(Debug, Default, Clone, Copy)]
+struct ConnectionLimits {
+    header_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    max_header_size: Option<usize>,
+    h2_max_concurrent_streams: Option<u32>,
+    h2_initial_stream_window_size: Option<u32>,
+    h2_initial_connection_window_size: Option<u32>,
+}
+
+struct Connection {
+    service: Service,
+    protocol: Protocol,
+    limits: ConnectionLimits,
+    shutdown: watch::Receiver<()>,
+    /// Frees a place for another connection with `max_connections`.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Connection {
+    /// Runs the TLS handshake and serves the connection with the protocol
+    /// picked through ALPN.
+    async fn serve_tls(mut self, tls: Arc<TlsAcceptor>, timeout: Duration, stream: TcpStream, mut conn: ConnectionInfo) {
+        let peer = conn.remote_addr;
+        let handshake = tokio::time::timeout(timeout, tls.accept(stream));
+        let result = tokio::select! {
+            result = handshake => result,
+            _ = self.shutdown.changed() => return,
+        };
+        match result {
+            Ok(Ok((stream, tls_info))) => {
+                tls.metrics.succeeded.fetch_add(1, Ordering::Relaxed);
+                if let Some(protocol) = Protocol::from_alpn(tls_info.alpn_protocol()) {
+                    self.protocol = protocol;
+                }
+                conn.tls = Some(tls_info);
+                self.service.conn = Arc::new(conn);
+                self.serve(stream).await
+            }
+            Ok(Err(err)) => {
+                tls.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                tracing::info!(peer = ?peer, error = %err, "TLS handshake failed");
+            }
+            Err(_) => {
+                tls.metrics.timed_out.fetch_add(1, Ordering::Relaxed);
+                tracing::info!(peer = ?peer, timeout = ?timeout, "TLS handshake timed out");
+            }
+        }
+    }
+
+    async fn serve<S>(mut self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match self.protocol {
+            Protocol::Auto => {
+                // the preface counts as part of the first request head, so a
+                // client sending nothing is dropped like one stalling a head
+                let timeout = self.limits.header_read_timeout.unwrap_or(DEFAULT_HEADER_READ_TIMEOUT);
+                let sniffed = tokio::select! {
+                    sniffed = tokio::time::timeout(timeout, sniff_h2(stream)) => sniffed,
+                    _ = self.shutdown.changed() => return,
+                };
+                let (is_h2, stream) = match sniffed {
+                    Ok(Ok(sniffed)) => sniffed,
+                    Ok(Err(err)) => {
+                        tracing::debug!(error = %err, "failed to read connection preface");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!(timeout = ?timeout, "timed out reading connection preface");
+                        return;
+                    }
+                };
+                self.protocol = if is_h2 { Protocol::HTTP2 } else { Protocol::HTTP1 };
+                self.serve_io(stream).await
+            }
+            _ => self.serve_io(stream).await,
+        }
+    }
+
+    async fn serve_io<S>(mut self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let stream = Idle::new(stream);
+        let idle = self.limits.keep_alive_timeout.map(|timeout| {
+            self.service.requests = Some(stream.requests());
+            stream.timeout(timeout)
+        });
+        let idle = async {
+            match idle {
+                Some(idle) => idle.await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(idle);
+        let io = TokioIo::new(stream);
+        let limits = self.limits;
+        match self.protocol {
+            Protocol::HTTP1 | Protocol::Auto => {
+                let mut builder = hyper::server::conn::http1::Builder::new();
+                builder.timer(TokioTimer).header_read_timeout(limits.header_read_timeout.unwrap_or(DEFAULT_HEADER_READ_TIMEOUT));
+                if let Some(max_header_size) = limits.max_header_size {
+                    builder.max_header_size(max_header_size);
+                }
+                let conn = builder.serve_connection(io, self.service);
+                tokio::pin!(conn);
+                let result = tokio::select! {
+                    result = conn.as_mut() => result,
+                    _ = self.shutdown.changed() => {
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                    _ = &mut idle => {
+                        tracing::debug!("closing idle connection");
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                };
+                if let Err(err) = result {
+                    tracing::debug!(error = %err, "failed to serve connection");
+                }
+            },
+            Protocol::HTTP2 => {
+                let mut builder = hyper::server::conn::http2::Builder::new(TokioExecutor);
+                if let Some(max_header_size) = limits.max_header_size {
+                    builder.max_header_list_size(u32::try_from(max_header_size).unwrap_or(u32::MAX));
+                }
+                if let Some(max_concurrent_streams) = limits.h2_max_concurrent_streams {
+                    builder.max_concurrent_streams(max_concurrent_streams);
+                }
+                if let Some(size) = limits.h2_initial_stream_window_size {
+                    builder.initial_stream_window_size(size);
+                }
+                if let Some(size) = limits.h2_initial_connection_window_size {
+                    builder.initial_connection_window_size(size);
+                }
+                let conn = builder.serve_connection(io, self.service);
+                tokio::pin!(conn);
+                let result = tokio::select! {
+                    result = conn.as_mut() => result,
+                    _ = self.shutdown.changed() => {
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                    _ = &mut idle => {
+                        tracing::debug!("closing idle connection");
+                        conn.as_mut().graceful_shutdown();
+                        conn.await
+                    }
+                };
+                if let Err(err) = result {
+                    tracing::debug!(error = %err, "failed to serve h2 connection");
+                }
+            },
+        };
+    }
+}
+
+/// The client connection preface of HTTP/2, RFC 9113 section 3.4.
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Reads until the stream either starts with the HTTP/2 preface or is known
+/// not to, and returns the stream with the bytes read put back.
+async fn sniff_h2<S: AsyncRead + Unpin>(mut stream: S) -> std::io::Result<(bool, Rewind<S>)> {
+    let mut buf = [0; H2_PREFACE.len()];
+    let mut len = 0;
+    let is_h2 = loop {
+        let read = stream.read(&mut buf[len..]).await?;
+        len += read;
+        if read == 0 || buf[..len] != H2_PREFACE[..len] {
+            break false;
+        }
+        if len == H2_PREFACE.len() {
+            break true;
+        }
+    };
+    Ok((is_h2, Rewind::new(stream, Bytes::copy_from_slice(&buf[..len]))))
+}
+
 #[derive(Debug, Clone, Copy)]
 enum  Protocol {
     HTTP1,
     HTTP2,
+    Auto,
+}
+
+impl Protocol {
+    /// Protocols offered to TLS clients through ALPN, by preference.
+    fn alpn_protocols(self) -> Vec<Vec<u8>> {
+        let protocols: &[&[u8]] = match self {
+            Protocol::HTTP1 => &[b"http/1.1"],
+            Protocol::HTTP2 => &[b"h2"],
+            Protocol::Auto => &[b"h2", b"http/1.1"],
+        };
+        protocols.iter().map(|protocol| protocol.to_vec()).collect()
+    }
+
+    fn from_alpn(protocol: Option<&str>) -> Option<Self> {
+        match protocol {
+            Some("h2") => Some(Protocol::HTTP2),
+            Some("http/1.1") => Some(Protocol::HTTP1),
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
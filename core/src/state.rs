@@ -0,0 +1,18 @@
+use std::{any::{Any, TypeId}, collections::HashMap, sync::Arc};
+
+/// Shared values registered with `ServerBuilder::state`, one per type.
+#[derive(Clone, Default)]
+pub(crate) struct StateMap {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl StateMap {
+    pub(crate) fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub(crate) fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let value = self.map.get(&TypeId::of::<T>())?.clone();
+        value.downcast::<T>().ok()
+    }
+}
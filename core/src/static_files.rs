@@ -0,0 +1,432 @@
+//! Serving files from a directory, see `ServerBuilder::static_dir`.
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{header, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::io::AsyncSeekExt;
+
+use crate::{
+    error::ReturnError,
+    request::{HeaderMap, HttpRequest},
+    response::{Body, Response},
+    RouteInfo,
+};
+
+/// Name of the catch-all parameter holding the file path below the prefix.
+const PATH_PARAM: &str = "path";
+
+/// Precompressed variants, in order of preference.
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Characters escaped in the segments of a redirect `Location`.
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>').add(b'?').add(b'\\').add(b'`').add(b'{').add(b'}');
+
+/// A directory served under a path prefix.
+///
+/// For `GET` and `HEAD` requests the file below the prefix is answered with
+/// its content type guessed from the extension, an `ETag` and a
+/// `Last-Modified` date. Conditional requests are answered with `304` and
+/// single byte ranges with `206`. Paths leaving the directory, also through
+/// a symlink, are answered with `404`.
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    root: PathBuf,
+    index_file: Option<String>,
+    precompressed: bool,
+    follow_symlinks: bool,
+}
+
+impl StaticDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            index_file: Some("index.html".to_string()),
+            precompressed: true,
+            follow_symlinks: false,
+        }
+    }
+
+    /// File served for a request naming a directory, `index.html` by default.
+    /// `None` answers such requests with `404`.
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(str::to_string);
+        self
+    }
+
+    /// Whether a `.br` or `.gz` file next to the requested one is sent instead
+    /// to clients accepting that encoding, enabled by default.
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Whether symlinks leading out of the directory are followed, off by
+    /// default. Links within the directory are always followed.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// The `GET {prefix}/*path` route serving this directory.
+    pub(crate) fn route(self, prefix: &str) -> RouteInfo {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let pattern = format!("{}/*{}", prefix, PATH_PARAM);
+        let dir = Arc::new(self);
+        RouteInfo::new(
+            "GET".to_string(),
+            crate::Path::new(&pattern),
+            Box::new(move |req| {
+                let dir = dir.clone();
+                let prefix = prefix.clone();
+                Box::pin(async move { Ok(dir.serve(req, &prefix).await) })
+            }),
+        )
+    }
+
+    async fn serve(&self, req: HttpRequest, prefix: &str) -> Response<Body> {
+        let relative = match req.path_params().get(PATH_PARAM).and_then(|path| relative_path(path)) {
+            Some(relative) => relative,
+            None => return not_found(),
+        };
+        let mut path = self.root.join(&relative);
+        let mut metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error(err),
+        };
+        if metadata.is_dir() {
+            // relative links in the index file resolve against the directory
+            if !req.path().ends_with('/') {
+                return Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(header::LOCATION, directory_location(prefix, &relative, req.uri().query()))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            match &self.index_file {
+                Some(index_file) => path.push(index_file),
+                None => return not_found(),
+            }
+            metadata = match tokio::fs::metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(err) => return io_error(err),
+            };
+        }
+        if !metadata.is_file() {
+            return not_found();
+        }
+        match self.is_inside(&path).await {
+            Ok(true) => {}
+            Ok(false) => return not_found(),
+            Err(err) => return io_error(err),
+        }
+
+        let headers = req.headers();
+        let content_type = content_type(&path);
+        // ranges are served from the uncompressed file only
+        let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+        let mut content_encoding = None;
+        if self.precompressed && range.is_none() {
+            if let Some((encoding, variant, variant_metadata)) = self.find_precompressed(&path, headers).await {
+                content_encoding = Some(encoding);
+                path = variant;
+                metadata = variant_metadata;
+            }
+        }
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = etag(len, modified);
+        let last_modified = modified.map(httpdate::fmt_http_date);
+
+        let mut builder = Response::builder()
+            .header(header::ETAG, &etag)
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(last_modified) = &last_modified {
+            builder = builder.header(header::LAST_MODIFIED, last_modified);
+        }
+        if self.precompressed {
+            builder = builder.header(header::VARY, "accept-encoding");
+        }
+        if is_not_modified(headers, &etag, modified) {
+            return builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+        }
+
+        let range = range.filter(|_| if_range_matches(headers, &etag, modified));
+        let (start, end) = match range.map(|range| parse_range(range, len)) {
+            Some(ByteRange::Satisfiable(start, end)) => {
+                builder = builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+                (start, end + 1)
+            }
+            Some(ByteRange::Unsatisfiable) => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            Some(ByteRange::Ignored) | None => (0, len),
+        };
+
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(err) => return io_error(err),
+        };
+        if start > 0 {
+            if let Err(err) = file.seek(io::SeekFrom::Start(start)).await {
+                return io_error(err);
+            }
+        }
+        builder = builder.header(header::CONTENT_TYPE, content_type);
+        if let Some(content_encoding) = content_encoding {
+            builder = builder.header(header::CONTENT_ENCODING, content_encoding);
+        }
+        builder.body(Body::file_range(file, end - start)).unwrap()
+    }
+
+    /// Whether `path` still is below the root once symlinks are resolved.
+    async fn is_inside(&self, path: &Path) -> io::Result<bool> {
+        if self.follow_symlinks {
+            return Ok(true);
+        }
+        let root = tokio::fs::canonicalize(&self.root).await?;
+        Ok(tokio::fs::canonicalize(path).await?.starts_with(root))
+    }
+
+    /// The `.br` or `.gz` file next to `path` matching the `Accept-Encoding`
+    /// of the request, with its encoding.
+    async fn find_precompressed(&self, path: &Path, headers: &HeaderMap) -> Option<(&'static str, PathBuf, std::fs::Metadata)> {
+        let accept_encoding = headers.get(header::ACCEPT_ENCODING)?.to_str().ok()?;
+        for (encoding, extension) in ENCODINGS {
+            if !accepts_encoding(accept_encoding, encoding) {
+                continue;
+            }
+            let mut variant = path.as_os_str().to_os_string();
+            variant.push(".");
+            variant.push(extension);
+            let variant = PathBuf::from(variant);
+            match tokio::fs::metadata(&variant).await {
+                Ok(metadata) if metadata.is_file() && matches!(self.is_inside(&variant).await, Ok(true)) => {
+                    return Some((encoding, variant, metadata));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// `Location` of the directory `relative` below `prefix`, with a trailing
+/// slash. Built from the normalized path rather than the request, empty
+/// segments are dropped so it never starts with `//`, another host.
+fn directory_location(prefix: &str, relative: &Path, query: Option<&str>) -> String {
+    let prefix = prefix.split('/').filter(|segment| !segment.is_empty()).map(str::to_string);
+    let relative = relative.components().map(|component| {
+        utf8_percent_encode(&component.as_os_str().to_string_lossy(), SEGMENT).to_string()
+    });
+    let mut location = String::new();
+    for segment in prefix.chain(relative) {
+        location.push('/');
+        location.push_str(&segment);
+    }
+    location.push('/');
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+/// Checks that the path below the prefix, already percent-decoded by the
+/// router, stays inside the directory: `..`, absolute paths and drive
+/// prefixes are refused.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in path.split('/') {
+        if segment.contains('\\') || segment.contains('\0') {
+            return None;
+        }
+        for component in Path::new(segment).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+    }
+    Some(relative)
+}
+
+fn content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    if mime.type_() == mime_guess::mime::TEXT || mime == mime_guess::mime::APPLICATION_JAVASCRIPT {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.essence_str().to_string()
+    }
+}
+
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|item| {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        coding.eq_ignore_ascii_case(encoding) && quality > 0.0
+    })
+}
+
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("\"{:x}-{:x}\"", modified, len)
+}
+
+/// Seconds since the epoch, the precision of HTTP dates.
+fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+}
+
+/// Evaluates `If-None-Match`, or `If-Modified-Since` when it is absent.
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let if_none_match = if_none_match.to_str().unwrap_or_default();
+        // weak comparison, a precompressed variant may be marked weak by a proxy
+        return if_none_match.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+        });
+    }
+    let since = headers.get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since.and_then(unix_secs), modified.and_then(unix_secs)) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// Whether the `Range` header applies: without `If-Range`, or when it names
+/// the current `ETag` (strongly) or `Last-Modified` date exactly.
+fn if_range_matches(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let if_range = match headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return if_range == etag;
+    }
+    match (httpdate::parse_http_date(if_range).ok().and_then(unix_secs), modified.and_then(unix_secs)) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false,
+    }
+}
+
+enum ByteRange {
+    /// First and last byte, inclusive.
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+    /// Malformed or multiple ranges, the whole file is sent.
+    Ignored,
+}
+
+fn parse_range(range: &str, len: u64) -> ByteRange {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Ignored,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Ignored,
+    };
+    if first.is_empty() {
+        // the last `suffix` bytes
+        return match last.parse::<u64>() {
+            Ok(suffix) if suffix > 0 && len > 0 => ByteRange::Satisfiable(len.saturating_sub(suffix), len - 1),
+            Ok(_) => ByteRange::Unsatisfiable,
+            Err(_) => ByteRange::Ignored,
+        };
+    }
+    let first = match first.parse::<u64>() {
+        Ok(first) => first,
+        Err(_) => return ByteRange::Ignored,
+    };
+    let last = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(last) if last >= first => last,
+            _ => return ByteRange::Ignored,
+        }
+    };
+    if first >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Satisfiable(first, last.min(len - 1))
+}
+
+fn not_found() -> Response<Body> {
+    ReturnError::new(404, "not found!".to_string()).into_response()
+}
+
+fn io_error(err: io::Error) -> Response<Body> {
+    match err.kind() {
+        io::ErrorKind::NotFound => not_found(),
+        io::ErrorKind::PermissionDenied => ReturnError::new(403, "forbidden".to_string()).into_response(),
+        _ => {
+            tracing::error!(error = %err, "failed to read static file");
+            ReturnError::new(500, "failed to read file".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(range: &str, len: u64) -> Option<(u64, u64)> {
+        match parse_range(range, len) {
+            ByteRange::Satisfiable(first, last) => Some((first, last)),
+            ByteRange::Unsatisfiable => None,
+            ByteRange::Ignored => Some((0, len - 1)),
+        }
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(range("bytes=90-200", 100), Some((90, 99)));
+        assert_eq!(range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(range("bytes=-200", 100), Some((0, 99)));
+        assert_eq!(range("bytes=100-", 100), None);
+        assert_eq!(range("bytes=-0", 100), None);
+    }
+
+    #[test]
+    fn ignores_malformed_and_multiple_ranges() {
+        for header in ["items=0-9", "bytes=9-0", "bytes=a-b", "bytes=0", "bytes=0-1,5-9"] {
+            assert!(matches!(parse_range(header, 100), ByteRange::Ignored), "{}", header);
+        }
+    }
+
+    #[test]
+    fn relative_path_stays_inside_the_directory() {
+        assert_eq!(relative_path("css/site.css"), Some(PathBuf::from("css/site.css")));
+        assert_eq!(relative_path("./css//site.css"), Some(PathBuf::from("css/site.css")));
+        assert_eq!(relative_path(""), Some(PathBuf::new()));
+        // the catch-all has no leading slash, one in the request stays below the root
+        assert_eq!(relative_path("/etc/passwd"), Some(PathBuf::from("etc/passwd")));
+        for path in ["../secret", "css/../../secret", "css/..", "..\\secret", "a\0b"] {
+            assert_eq!(relative_path(path), None, "{:?}", path);
+        }
+    }
+}
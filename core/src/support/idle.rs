@@ -0,0 +1,152 @@
+//! An IO wrapper remembering when the stream was last read from or written to.
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Instant,
+};
+
+/// Records the time of every read or write on `inner` that made progress,
+/// and the requests in flight, so that the connection can be closed after a
+/// period without traffic between requests.
+pub struct Idle<T> {
+    inner: T,
+    activity: Arc<Mutex<Activity>>,
+}
+
+struct Activity {
+    last_active: Instant,
+    in_flight: usize,
+}
+
+impl<T> Idle<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            activity: Arc::new(Mutex::new(Activity {
+                last_active: Instant::now(),
+                in_flight: 0,
+            })),
+        }
+    }
+
+    /// Completes once there was no traffic for `timeout` and no request is in
+    /// flight. A request counts from the start of its handler until its
+    /// response body has been sent, so a slow handler or body never trips it.
+    pub fn timeout(&self, timeout: Duration) -> impl std::future::Future<Output = ()> {
+        let activity = self.activity.clone();
+        async move {
+            loop {
+                let deadline = {
+                    let activity = activity.lock().unwrap();
+                    // while busy, check again later; `last_active` is set when the
+                    // last request ends, so the deadline is still exact
+                    if activity.in_flight > 0 {
+                        Instant::now() + timeout
+                    } else {
+                        let deadline = activity.last_active + timeout;
+                        if deadline <= Instant::now() {
+                            return;
+                        }
+                        deadline
+                    }
+                };
+                tokio::time::sleep_until(deadline).await;
+            }
+        }
+    }
+
+    /// A handle to mark the requests of this connection as in flight.
+    pub fn requests(&self) -> InFlight {
+        InFlight {
+            activity: self.activity.clone(),
+        }
+    }
+
+    fn touch<R>(&self, poll: &Poll<io::Result<R>>) {
+        if let Poll::Ready(Ok(_)) = poll {
+            self.activity.lock().unwrap().last_active = Instant::now();
+        }
+    }
+}
+
+/// Counts the requests in flight on a connection wrapped in [`Idle`].
+#[derive(Clone)]
+pub struct InFlight {
+    activity: Arc<Mutex<Activity>>,
+}
+
+impl InFlight {
+    /// Marks a request as in flight until the returned guard is dropped.
+    pub fn start(&self) -> Busy {
+        self.activity.lock().unwrap().in_flight += 1;
+        Busy {
+            activity: self.activity.clone(),
+        }
+    }
+}
+
+/// A request in flight, see [`InFlight::start`].
+pub struct Busy {
+    activity: Arc<Mutex<Activity>>,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut activity = self.activity.lock().unwrap();
+        activity.in_flight -= 1;
+        activity.last_active = Instant::now();
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Idle<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.touch(&poll);
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Idle<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.touch(&poll);
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.touch(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1 +1,3 @@
-pub mod tokiort;
\ No newline at end of file
+pub mod idle;
+pub mod rewind;
+pub mod tokiort;
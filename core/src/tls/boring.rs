@@ -0,0 +1,214 @@
+//! TLS with BoringSSL, through `boring` and `tokio-boring`.
+use std::{collections::HashMap, io};
+
+use ::boring::{
+    error::ErrorStack,
+    ssl::{AlpnError, NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslMethod, SslRef, SslVerifyMode},
+    x509::{X509Name, X509Ref, X509VerifyResult},
+};
+use foreign_types::ForeignTypeRef;
+use tokio::net::TcpStream;
+
+use super::{find_by_server_name, AcceptFuture, BoxError, CertFiles, ClientAuth, SslFiletype, TlsBackend, TlsConfig};
+use crate::request::TlsInfo;
+
+#[derive(Clone)]
+pub(crate) struct BoringTls(SslAcceptor);
+
+impl TlsBackend for BoringTls {
+    type Stream = tokio_boring::SslStream<TcpStream>;
+
+    fn check(files: &CertFiles) -> Result<(), BoxError> {
+        load(files).map(|_| ())
+    }
+
+    fn new(config: &TlsConfig) -> Result<Self, BoxError> {
+        let mut contexts = HashMap::new();
+        for (server_name, files) in &config.sni {
+            let mut acceptor = load(files)?;
+            configure(&mut acceptor, config)?;
+            contexts.insert(server_name.to_ascii_lowercase(), acceptor.build().into_context());
+        }
+
+        let mut acceptor = load(&config.default)?;
+        configure(&mut acceptor, config)?;
+        if !contexts.is_empty() {
+            acceptor.set_servername_callback(move |ssl, _| {
+                let server_name = ssl.servername(NameType::HOST_NAME).map(str::to_ascii_lowercase);
+                if let Some(context) = server_name.and_then(|name| find_by_server_name(&contexts, &name)) {
+                    ssl.set_ssl_context(context).map_err(|_| SniError::ALERT_FATAL)?;
+                }
+                Ok(())
+            });
+        }
+        Ok(BoringTls(acceptor.build()))
+    }
+
+    fn accept(&self, stream: TcpStream) -> AcceptFuture<Self::Stream> {
+        let acceptor = self.0.clone();
+        Box::pin(async move {
+            let stream = tokio_boring::accept(&acceptor, stream).await
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            let info = tls_info(stream.ssl());
+            Ok((stream, info))
+        })
+    }
+}
+
+fn load(files: &CertFiles) -> Result<SslAcceptorBuilder, BoxError> {
+    let file_type = match files.file_type {
+        SslFiletype::PEM => ::boring::ssl::SslFiletype::PEM,
+        SslFiletype::ASN1 => ::boring::ssl::SslFiletype::ASN1,
+    };
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key_file(&files.private_key_file, file_type)?;
+    acceptor.set_certificate_chain_file(&files.cert_file)?;
+    // e.g. only one of them was replaced, every handshake would fail
+    acceptor.check_private_key().map_err(|err| {
+        format!("private key `{}` does not match the certificate in `{}`: {}", files.private_key_file, files.cert_file, err)
+    })?;
+    Ok(acceptor)
+}
+
+/// Client verification and ALPN. They are set on every context: once the
+/// servername callback switches to another one, the client is verified with
+/// its trust store and the protocol chosen by its ALPN callback.
+fn configure(acceptor: &mut SslAcceptorBuilder, config: &TlsConfig) -> Result<(), BoxError> {
+    if let Some(client_auth) = &config.client_auth {
+        configure_client_auth(acceptor, client_auth)?;
+    }
+    let offered: Vec<u8> = config.alpn_protocols.iter()
+        .flat_map(|proto| std::iter::once(proto.len() as u8).chain(proto.iter().copied()))
+        .collect();
+    acceptor.set_alpn_select_callback(move |_, client| {
+        ::boring::ssl::select_next_proto(&offered, client).ok_or(AlpnError::NOACK)
+    });
+    Ok(())
+}
+
+fn configure_client_auth(acceptor: &mut SslAcceptorBuilder, client_auth: &ClientAuth) -> Result<(), BoxError> {
+    acceptor.set_ca_file(&client_auth.ca_file)?;
+    acceptor.set_client_ca_list(X509Name::load_client_ca_file(&client_auth.ca_file)?);
+    let mode = if client_auth.required {
+        SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+    } else {
+        SslVerifyMode::PEER
+    };
+    acceptor.set_verify(mode);
+    if let Some(verify_depth) = client_auth.verify_depth {
+        acceptor.set_verify_depth(verify_depth);
+    }
+    if let Some(crl_file) = &client_auth.crl_file {
+        add_crls(acceptor, crl_file)?;
+    }
+    Ok(())
+}
+
+/// Adds the CRLs in `crl_file` to the store client certificates are verified
+/// with and turns on checking them. Certificates in the file are refused,
+/// they would become trusted CAs. boring has no safe wrappers for this.
+fn add_crls(acceptor: &mut SslAcceptorBuilder, crl_file: &str) -> Result<(), BoxError> {
+    let pem = std::fs::read_to_string(crl_file)?;
+    let crls = pem_blocks(&pem).map_err(|err| format!("failed to read `{}`: {}", crl_file, err))?;
+    if crls.is_empty() {
+        return Err(format!("no CRL found in `{}`", crl_file).into());
+    }
+    if let Some((label, _)) = crls.iter().find(|(label, _)| *label != "X509 CRL") {
+        return Err(format!("`{}` may only contain CRLs, found a `{}`", crl_file, label).into());
+    }
+
+    let store = acceptor.cert_store_mut().as_ptr();
+    for (_, der) in &crls {
+        unsafe {
+            let mut input = der.as_ptr();
+            let crl = boring_sys::d2i_X509_CRL(std::ptr::null_mut(), &mut input, der.len() as _);
+            if crl.is_null() {
+                return Err(format!("invalid CRL in `{}`: {}", crl_file, ErrorStack::get()).into());
+            }
+            // the store takes its own reference
+            let added = boring_sys::X509_STORE_add_crl(store, crl);
+            boring_sys::X509_CRL_free(crl);
+            if added != 1 {
+                return Err(ErrorStack::get().into());
+            }
+        }
+    }
+    let flags = boring_sys::X509_V_FLAG_CRL_CHECK | boring_sys::X509_V_FLAG_CRL_CHECK_ALL;
+    unsafe {
+        boring_sys::X509_STORE_set_flags(store, flags as _);
+    }
+    Ok(())
+}
+
+/// The label and DER contents of each `-----BEGIN {label}-----` block.
+fn pem_blocks(pem: &str) -> Result<Vec<(&str, Vec<u8>)>, BoxError> {
+    let mut blocks = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let (label, after) = rest[start + "-----BEGIN ".len()..].split_once("-----").ok_or("malformed PEM header")?;
+        let end = format!("-----END {}-----", label);
+        let (body, after) = after.split_once(end.as_str()).ok_or_else(|| format!("unterminated PEM `{}`", label))?;
+        let body: String = body.split_whitespace().collect();
+        blocks.push((label, ::boring::base64::decode_block(&body)?));
+        rest = after;
+    }
+    Ok(blocks)
+}
+
+fn tls_info(ssl: &SslRef) -> TlsInfo {
+    // on the server side the peer chain does not include the client's own certificate
+    let mut peer_certificates = Vec::new();
+    if let Some(cert) = ssl.peer_certificate() {
+        peer_certificates.extend(cert.to_der());
+        if let Some(chain) = ssl.peer_cert_chain() {
+            peer_certificates.extend(chain.iter().filter_map(|cert| cert.to_der().ok()));
+        }
+    }
+    // a certificate that failed verification only gets here without client auth
+    let verified = ssl.peer_certificate().filter(|_| ssl.verify_result() == X509VerifyResult::OK);
+    TlsInfo {
+        version: ssl.version_str().to_string(),
+        cipher: ssl.current_cipher().map(|cipher| cipher.name().to_string()),
+        server_name: ssl.servername(NameType::HOST_NAME).map(str::to_string),
+        alpn_protocol: ssl.selected_alpn_protocol().map(|proto| String::from_utf8_lossy(proto).into_owned()),
+        peer_certificates,
+        peer_subject: verified.as_ref().map(|cert| subject(cert)),
+        peer_subject_alt_names: verified.as_ref().map(|cert| subject_alt_names(cert)).unwrap_or_default(),
+    }
+}
+
+/// `C=US, O=Example, CN=client`
+fn subject(cert: &X509Ref) -> String {
+    cert.subject_name()
+        .entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().as_utf8().map(|value| value.to_string()).unwrap_or_default();
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `DNS:api.internal`, `URI:spiffe://example/service`, `email:...` and `IP:10.0.0.1`.
+fn subject_alt_names(cert: &X509Ref) -> Vec<String> {
+    let names = match cert.subject_alt_names() {
+        Some(names) => names,
+        None => return Vec::new(),
+    };
+    names.iter().filter_map(|name| {
+        if let Some(dns) = name.dnsname() {
+            Some(format!("DNS:{}", dns))
+        } else if let Some(uri) = name.uri() {
+            Some(format!("URI:{}", uri))
+        } else if let Some(email) = name.email() {
+            Some(format!("email:{}", email))
+        } else {
+            let ip = match name.ipaddress()? {
+                [a, b, c, d] => std::net::IpAddr::from([*a, *b, *c, *d]),
+                ip => std::net::IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+            };
+            Some(format!("IP:{}", ip))
+        }
+    }).collect()
+}
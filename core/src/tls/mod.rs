@@ -0,0 +1,306 @@
+//! TLS for `ServerBuilder::ssl`.
+//!
+//! The backend is chosen with cargo features: `boring` (the default) or
+//! `rustls`. When both are enabled `boring` is used, and without either the
+//! server only speaks cleartext HTTP.
+#![cfg_attr(not(any(feature = "boring", feature = "rustls")), allow(dead_code))]
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{io::{AsyncRead, AsyncWrite}, net::TcpStream, sync::watch};
+
+use crate::request::TlsInfo;
+
+#[cfg(feature = "boring")]
+mod boring;
+#[cfg(feature = "rustls")]
+mod rustls;
+
+#[cfg(feature = "boring")]
+type Backend = self::boring::BoringTls;
+#[cfg(all(feature = "rustls", not(feature = "boring")))]
+type Backend = self::rustls::RustlsTls;
+#[cfg(not(any(feature = "boring", feature = "rustls")))]
+type Backend = NoTls;
+
+type BoxError = Box<dyn std::error::Error>;
+
+pub(crate) type AcceptFuture<S> = Pin<Box<dyn Future<Output = io::Result<(S, TlsInfo)>> + Send>>;
+
+/// A TLS implementation the server accepts connections with.
+pub(crate) trait TlsBackend: Clone + Send + Sync + Sized + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Loads a private key and certificate chain, to report bad files early.
+    fn check(files: &CertFiles) -> Result<(), BoxError>;
+
+    fn new(config: &TlsConfig) -> Result<Self, BoxError>;
+
+    /// Runs the handshake and describes the negotiated session.
+    fn accept(&self, stream: TcpStream) -> AcceptFuture<Self::Stream>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SslFiletype {
+    PEM,
+    ASN1,
+}
+
+/// Client certificate verification, see `ServerBuilder::client_auth`.
+#[derive(Debug, Clone)]
+pub struct ClientAuth {
+    ca_file: String,
+    required: bool,
+    verify_depth: Option<u32>,
+    crl_file: Option<String>,
+}
+
+impl ClientAuth {
+    /// Rejects clients without a certificate issued by one of the CAs in
+    /// `ca_file` (PEM).
+    pub fn required(ca_file: &str) -> Self {
+        Self {
+            ca_file: ca_file.to_string(),
+            required: true,
+            verify_depth: None,
+            crl_file: None,
+        }
+    }
+
+    /// Asks for a client certificate but also accepts clients without one.
+    /// A certificate that is sent must still verify against `ca_file`.
+    pub fn optional(ca_file: &str) -> Self {
+        Self {
+            required: false,
+            ..Self::required(ca_file)
+        }
+    }
+
+    /// Maximum number of intermediate certificates between the client
+    /// certificate and the CA. Not supported by the `rustls` backend.
+    pub fn verify_depth(mut self, verify_depth: u32) -> Self {
+        self.verify_depth = Some(verify_depth);
+        self
+    }
+
+    /// Rejects revoked certificates, using the PEM encoded CRLs in `crl_file`.
+    /// The file may only contain CRLs, certificates there are refused.
+    /// With `boring` every CA of the chain needs a CRL there, or verification
+    /// fails; `rustls` only checks the certificates whose issuer has one.
+    pub fn crl_file(mut self, crl_file: &str) -> Self {
+        self.crl_file = Some(crl_file.to_string());
+        self
+    }
+}
+
+/// A private key and certificate chain given to `ServerBuilder::ssl` or
+/// `ssl_sni`. The file type applies to the key, the chain is always PEM.
+#[derive(Debug, Clone)]
+pub(crate) struct CertFiles {
+    pub(crate) private_key_file: String,
+    pub(crate) cert_file: String,
+    pub(crate) file_type: SslFiletype,
+}
+
+impl CertFiles {
+    pub(crate) fn check(&self) -> Result<(), BoxError> {
+        Backend::check(self)
+    }
+}
+
+/// Everything the TLS acceptor is built from. The acceptor is created by
+/// `build`, once the protocols to offer through ALPN are known, and again
+/// whenever the certificates are reloaded.
+#[derive(Debug, Clone)]
+pub(crate) struct TlsConfig {
+    pub(crate) default: CertFiles,
+    pub(crate) sni: Vec<(String, CertFiles)>,
+    /// ALPN protocol names by preference, e.g. `h2`.
+    pub(crate) alpn_protocols: Vec<Vec<u8>>,
+    pub(crate) client_auth: Option<ClientAuth>,
+}
+
+impl TlsConfig {
+    /// Latest modification time of the files the acceptor is built from.
+    fn modified(&self) -> Option<SystemTime> {
+        let mut files = vec![&self.default.private_key_file, &self.default.cert_file];
+        for (_, cert) in &self.sni {
+            files.push(&cert.private_key_file);
+            files.push(&cert.cert_file);
+        }
+        if let Some(client_auth) = &self.client_auth {
+            files.push(&client_auth.ca_file);
+            files.extend(&client_auth.crl_file);
+        }
+        files.into_iter()
+            .filter_map(|file| std::fs::metadata(file).and_then(|meta| meta.modified()).ok())
+            .max()
+    }
+}
+
+/// Looks up the certificate for `server_name`, then for a `*.` wildcard
+/// covering its first label.
+fn find_by_server_name<'a, T>(certs: &'a HashMap<String, T>, server_name: &str) -> Option<&'a T> {
+    certs.get(server_name).or_else(|| {
+        let (_, parent) = server_name.split_once('.')?;
+        certs.get(&format!("*.{}", parent))
+    })
+}
+
+/// Outcomes of the TLS handshakes since the server was built, see
+/// `Server::tls_metrics`.
+#[derive(Debug, Default)]
+pub struct TlsMetrics {
+    pub(crate) succeeded: AtomicU64,
+    pub(crate) failed: AtomicU64,
+    pub(crate) timed_out: AtomicU64,
+}
+
+impl TlsMetrics {
+    pub fn handshakes_succeeded(&self) -> u64 {
+        self.succeeded.load(Ordering::Relaxed)
+    }
+
+    /// Handshakes the client or the server aborted, e.g. for an unknown
+    /// certificate authority or a client that does not speak TLS.
+    pub fn handshakes_failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Handshakes that did not complete within `ServerBuilder::tls_handshake_timeout`.
+    pub fn handshakes_timed_out(&self) -> u64 {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+}
+
+/// The acceptor for new TLS connections, replaced when the certificates are
+/// reloaded. Open connections keep the session they were accepted with.
+pub(crate) struct TlsAcceptor {
+    config: TlsConfig,
+    current: RwLock<Backend>,
+    modified: Mutex<Option<SystemTime>>,
+    pub(crate) metrics: Arc<TlsMetrics>,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(config: TlsConfig) -> Result<Self, BoxError> {
+        let modified = config.modified();
+        let acceptor = Backend::new(&config)?;
+        Ok(Self {
+            config,
+            current: RwLock::new(acceptor),
+            modified: Mutex::new(modified),
+            metrics: Arc::default(),
+        })
+    }
+
+    pub(crate) fn accept(&self, stream: TcpStream) -> AcceptFuture<<Backend as TlsBackend>::Stream> {
+        let current = self.current.read().unwrap().clone();
+        current.accept(stream)
+    }
+
+    /// Rebuilds the acceptor from the files, keeping the current one if they
+    /// can not be loaded, e.g. a key that does not match its certificate yet.
+    /// The files then still count as modified, so the next check retries.
+    fn reload(&self) {
+        // read before loading, a file replaced meanwhile is picked up next time
+        let modified = self.config.modified();
+        match Backend::new(&self.config) {
+            Ok(acceptor) => {
+                *self.current.write().unwrap() = acceptor;
+                *self.modified.lock().unwrap() = modified;
+                tracing::info!("reloaded TLS certificates");
+            }
+            Err(err) => tracing::error!(error = %err, "failed to reload TLS certificates, keeping the current ones"),
+        }
+    }
+
+    fn is_modified(&self) -> bool {
+        self.config.modified() > *self.modified.lock().unwrap()
+    }
+}
+
+/// Reloads the certificates on SIGHUP if `on_sighup` is set and, with an
+/// interval, whenever one of the files changes.
+pub(crate) async fn reload_tls(tls: Arc<TlsAcceptor>, interval: Option<Duration>, on_sighup: bool, mut shutdown: watch::Receiver<()>) {
+    #[cfg(unix)]
+    let mut hangup = if on_sighup {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                tracing::error!(error = %err, "failed to listen for SIGHUP");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    if on_sighup {
+        tracing::warn!("reloading TLS certificates on SIGHUP is only supported on unix");
+    }
+    let mut poll = interval.map(|interval| {
+        let mut poll = tokio::time::interval(interval);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        poll
+    });
+
+    loop {
+        let signal = async {
+            #[cfg(unix)]
+            if let Some(hangup) = &mut hangup {
+                hangup.recv().await;
+                return;
+            }
+            std::future::pending::<()>().await
+        };
+        let tick = async {
+            match &mut poll {
+                Some(poll) => {
+                    poll.tick().await;
+                }
+                None => std::future::pending::<()>().await,
+            }
+        };
+        tokio::select! {
+            _ = signal => tls.reload(),
+            _ = tick => {
+                if tls.is_modified() {
+                    tls.reload();
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+/// Stands in for a TLS backend when argos is built without one.
+#[cfg(not(any(feature = "boring", feature = "rustls")))]
+#[derive(Clone)]
+pub(crate) struct NoTls;
+
+#[cfg(not(any(feature = "boring", feature = "rustls")))]
+const NO_BACKEND: &str = "argos was built without TLS, enable the `boring` or `rustls` feature";
+
+#[cfg(not(any(feature = "boring", feature = "rustls")))]
+impl TlsBackend for NoTls {
+    type Stream = TcpStream;
+
+    fn check(_: &CertFiles) -> Result<(), BoxError> {
+        Err(NO_BACKEND.into())
+    }
+
+    fn new(_: &TlsConfig) -> Result<Self, BoxError> {
+        Err(NO_BACKEND.into())
+    }
+
+    fn accept(&self, _: TcpStream) -> AcceptFuture<Self::Stream> {
+        Box::pin(std::future::ready(Err(io::Error::new(io::ErrorKind::Other, NO_BACKEND))))
+    }
+}
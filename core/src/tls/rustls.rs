@@ -0,0 +1,217 @@
+//! TLS with rustls, through `tokio-rustls`.
+use std::{collections::HashMap, fs::File, io::BufReader, sync::Arc};
+
+use ::rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert, UnparsedCertRevocationList},
+    sign::{CertifiedKey, SigningKey},
+    Certificate, PrivateKey, ProtocolVersion, RootCertStore, ServerConfig, ServerConnection, SignatureScheme,
+};
+use tokio::net::TcpStream;
+use x509_parser::extensions::GeneralName;
+
+use super::{find_by_server_name, AcceptFuture, BoxError, CertFiles, SslFiletype, TlsBackend, TlsConfig};
+use crate::request::TlsInfo;
+
+#[derive(Clone)]
+pub(crate) struct RustlsTls(tokio_rustls::TlsAcceptor);
+
+impl TlsBackend for RustlsTls {
+    type Stream = tokio_rustls::server::TlsStream<TcpStream>;
+
+    fn check(files: &CertFiles) -> Result<(), BoxError> {
+        certified_key(files).map(|_| ())
+    }
+
+    fn new(config: &TlsConfig) -> Result<Self, BoxError> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &config.client_auth {
+            Some(client_auth) => {
+                if client_auth.verify_depth.is_some() {
+                    return Err("`ClientAuth::verify_depth` is not supported by the rustls backend".into());
+                }
+                let mut roots = RootCertStore::empty();
+                for cert in cert_chain(&client_auth.ca_file)? {
+                    roots.add(&cert)?;
+                }
+                let crls = match &client_auth.crl_file {
+                    Some(crl_file) => crls(crl_file)?,
+                    None => Vec::new(),
+                };
+                let crls = crls.into_iter().map(UnparsedCertRevocationList);
+                let invalid_crl = ::rustls::Error::InvalidCertRevocationList;
+                if client_auth.required {
+                    let verifier = AllowAnyAuthenticatedClient::new(roots).with_crls(crls).map_err(invalid_crl)?;
+                    builder.with_client_cert_verifier(verifier.boxed())
+                } else {
+                    let verifier = AllowAnyAnonymousOrAuthenticatedClient::new(roots).with_crls(crls).map_err(invalid_crl)?;
+                    builder.with_client_cert_verifier(verifier.boxed())
+                }
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut sni = HashMap::new();
+        for (server_name, files) in &config.sni {
+            sni.insert(server_name.to_ascii_lowercase(), certified_key(files)?);
+        }
+        let resolver = CertResolver {
+            default: certified_key(&config.default)?,
+            sni,
+        };
+        let mut server_config = builder.with_cert_resolver(Arc::new(resolver));
+        server_config.alpn_protocols = config.alpn_protocols.clone();
+        Ok(RustlsTls(Arc::new(server_config).into()))
+    }
+
+    fn accept(&self, stream: TcpStream) -> AcceptFuture<Self::Stream> {
+        let accept = self.0.accept(stream);
+        Box::pin(async move {
+            let stream = accept.await?;
+            let info = tls_info(stream.get_ref().1);
+            Ok((stream, info))
+        })
+    }
+}
+
+/// Picks the certificate by SNI, like the boring servername callback.
+struct CertResolver {
+    default: Arc<CertifiedKey>,
+    sni: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name().map(str::to_ascii_lowercase);
+        let cert = server_name.and_then(|name| find_by_server_name(&self.sni, &name));
+        Some(cert.unwrap_or(&self.default).clone())
+    }
+}
+
+fn certified_key(files: &CertFiles) -> Result<Arc<CertifiedKey>, BoxError> {
+    let key = ::rustls::sign::any_supported_type(&private_key(files)?)?;
+    let chain = cert_chain(&files.cert_file)?;
+    // e.g. only one of them was replaced, every handshake would fail
+    if !key_matches(key.as_ref(), &chain[0]) {
+        return Err(format!(
+            "private key `{}` does not match the certificate in `{}`",
+            files.private_key_file, files.cert_file,
+        ).into());
+    }
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+/// Whether a message signed with `key` verifies with the public key of `cert`.
+fn key_matches(key: &dyn SigningKey, cert: &Certificate) -> bool {
+    const MESSAGE: &[u8] = b"argos private key check";
+    let signer = match key.choose_scheme(&[
+        SignatureScheme::RSA_PKCS1_SHA256,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::ED25519,
+    ]) {
+        Some(signer) => signer,
+        None => return false,
+    };
+    let algorithm: &dyn ring::signature::VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::RSA_PKCS1_SHA256 => &ring::signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &ring::signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &ring::signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::ED25519 => &ring::signature::ED25519,
+        _ => return false,
+    };
+    let (signature, cert) = match (signer.sign(MESSAGE), x509_parser::parse_x509_certificate(&cert.0)) {
+        (Ok(signature), Ok((_, cert))) => (signature, cert),
+        _ => return false,
+    };
+    ring::signature::UnparsedPublicKey::new(algorithm, &cert.public_key().subject_public_key.data)
+        .verify(MESSAGE, &signature)
+        .is_ok()
+}
+
+fn private_key(files: &CertFiles) -> Result<PrivateKey, BoxError> {
+    let file = &files.private_key_file;
+    match files.file_type {
+        SslFiletype::ASN1 => Ok(PrivateKey(std::fs::read(file)?)),
+        SslFiletype::PEM => {
+            let mut reader = BufReader::new(File::open(file)?);
+            while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+                match item {
+                    rustls_pemfile::Item::RSAKey(key)
+                    | rustls_pemfile::Item::PKCS8Key(key)
+                    | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+                    _ => {}
+                }
+            }
+            Err(format!("no private key found in `{}`", file).into())
+        }
+    }
+}
+
+fn cert_chain(file: &str) -> Result<Vec<Certificate>, BoxError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(file)?))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in `{}`", file).into());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// The CRLs in `file`, refusing other items like boring does for the
+/// certificates that would become trusted CAs there.
+fn crls(file: &str) -> Result<Vec<Vec<u8>>, BoxError> {
+    let mut crls = Vec::new();
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(file)?))? {
+        match item {
+            rustls_pemfile::Item::Crl(crl) => crls.push(crl),
+            _ => return Err(format!("`{}` may only contain CRLs", file).into()),
+        }
+    }
+    if crls.is_empty() {
+        return Err(format!("no CRL found in `{}`", file).into());
+    }
+    Ok(crls)
+}
+
+fn tls_info(conn: &ServerConnection) -> TlsInfo {
+    let peer_certificates: Vec<Vec<u8>> = conn.peer_certificates()
+        .map(|certs| certs.iter().map(|cert| cert.0.clone()).collect())
+        .unwrap_or_default();
+    // rustls only finishes the handshake once a client certificate is verified
+    let verified = peer_certificates.first()
+        .and_then(|der| x509_parser::parse_x509_certificate(der).ok())
+        .map(|(_, cert)| cert);
+    TlsInfo {
+        version: match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(version) => format!("{:?}", version),
+            None => String::new(),
+        },
+        cipher: conn.negotiated_cipher_suite().map(|suite| format!("{:?}", suite.suite())),
+        server_name: conn.server_name().map(str::to_string),
+        alpn_protocol: conn.alpn_protocol().map(|proto| String::from_utf8_lossy(proto).into_owned()),
+        peer_subject: verified.as_ref().map(|cert| cert.subject().to_string()),
+        peer_subject_alt_names: verified.as_ref().map(subject_alt_names).unwrap_or_default(),
+        peer_certificates,
+    }
+}
+
+/// `DNS:api.internal`, `URI:spiffe://example/service`, `email:...` and `IP:10.0.0.1`.
+fn subject_alt_names(cert: &x509_parser::certificate::X509Certificate) -> Vec<String> {
+    let names = match cert.subject_alternative_name() {
+        Ok(Some(names)) => names.value,
+        _ => return Vec::new(),
+    };
+    names.general_names.iter().filter_map(|name| match name {
+        GeneralName::DNSName(dns) => Some(format!("DNS:{}", dns)),
+        GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+        GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+        GeneralName::IPAddress(ip) => {
+            let ip = match ip {
+                [a, b, c, d] => std::net::IpAddr::from([*a, *b, *c, *d]),
+                ip => std::net::IpAddr::from(<[u8; 16]>::try_from(*ip).ok()?),
+            };
+            Some(format!("IP:{}", ip))
+        }
+        _ => None,
+    }).collect()
+}
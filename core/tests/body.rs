@@ -0,0 +1,89 @@
+// `#[route]` registers through a ctor guarded by the `used_linker` cfg
+#![allow(unexpected_cfgs)]
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use argos::{error::ReturnError, request::HttpRequest, server::Server};
+use argos_macros::{route, register};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+#[route(POST, path = "/body/text", formatter = "text")]
+pub async fn text(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    req.text().await
+}
+
+#[route(POST, path = "/body/json", formatter = "text")]
+pub async fn json(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let value = req.json::<HashMap<String, u32>>().await?;
+    Ok(format!("{:?}", value.get("n")))
+}
+
+#[route(POST, path = "/body/form", formatter = "text")]
+pub async fn form(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let value = req.form::<HashMap<String, u32>>().await?;
+    Ok(format!("{:?}", value.get("n")))
+}
+
+/// The body limit of the test server, in bytes.
+const LIMIT: usize = 16;
+
+async fn serve() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder_from_listener(listener).max_body_size(LIMIT).build().await.unwrap();
+    tokio::spawn(async move {
+        server.start_with_shutdown(std::future::pending()).await.unwrap();
+    });
+    addr
+}
+
+/// Sends a `POST` of `body` to `path`, chunked or with a `Content-Length`,
+/// and returns the status and body of the response.
+async fn post(addr: SocketAddr, path: &str, body: &[u8], chunked: bool) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", path).into_bytes();
+    if chunked {
+        request.extend_from_slice(format!("Transfer-Encoding: chunked\r\n\r\n{:x}\r\n", body.len()).as_bytes());
+        request.extend_from_slice(body);
+        request.extend_from_slice(b"\r\n0\r\n\r\n");
+    } else {
+        request.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        request.extend_from_slice(body);
+    }
+    stream.write_all(&request).await.unwrap();
+    // the server may close before reading a rejected body, keep what arrived
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    let response = String::from_utf8_lossy(&response);
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[tokio::test]
+async fn bodies_within_the_limit_are_read() {
+    let addr = serve().await;
+    assert_eq!(post(addr, "/body/text", b"hello", false).await, (200, "hello".to_string()));
+    assert_eq!(post(addr, "/body/text", b"hello", true).await, (200, "hello".to_string()));
+    assert_eq!(post(addr, "/body/json", br#"{"n": 1}"#, false).await, (200, "Some(1)".to_string()));
+    assert_eq!(post(addr, "/body/form", b"n=2", false).await, (200, "Some(2)".to_string()));
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_are_rejected_with_413() {
+    let addr = serve().await;
+    let body = [b'a'; LIMIT + 1];
+    // refused from the `Content-Length`, and while reading a chunked body
+    assert_eq!(post(addr, "/body/text", &body, false).await.0, 413);
+    assert_eq!(post(addr, "/body/text", &body, true).await.0, 413);
+    assert_eq!(post(addr, "/body/text", &[b'a'; LIMIT], false).await.0, 200);
+}
+
+#[tokio::test]
+async fn malformed_bodies_are_rejected_with_400() {
+    let addr = serve().await;
+    assert_eq!(post(addr, "/body/text", b"\xff\xfe", false).await.0, 400);
+    assert_eq!(post(addr, "/body/json", b"{\"n\":", false).await.0, 400);
+    assert_eq!(post(addr, "/body/json", br#"{"n": "x"}"#, false).await.0, 400);
+    assert_eq!(post(addr, "/body/form", b"n=x", false).await.0, 400);
+}
@@ -0,0 +1,44 @@
+#!/bin/sh
+# Regenerates the certificates of the TLS tests, valid for 100 years.
+set -e
+cd "$(dirname "$0")"
+days=36500
+
+ca() {
+    openssl req -x509 -newkey rsa:2048 -nodes -keyout "$1.key" -out "$1.crt" -days $days -subj "/CN=$2" \
+        -addext basicConstraints=critical,CA:TRUE -addext keyUsage=critical,keyCertSign,cRLSign
+}
+
+# leaf NAME CA SUBJECT EXTENSIONS
+leaf() {
+    openssl req -newkey rsa:2048 -nodes -keyout "$1.key" -out "$1.csr" -subj "/CN=$3"
+    printf '%s\n' "$4" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA "$2.crt" -CAkey "$2.key" -CAcreateserial -days $days -out "$1.crt" -extfile "$1.ext"
+    rm "$1.csr" "$1.ext"
+}
+
+ca ca "argos test CA"
+ca other-ca "other test CA"
+leaf server ca localhost "subjectAltName=DNS:localhost
+extendedKeyUsage=serverAuth"
+leaf api ca api.example.com "subjectAltName=DNS:api.example.com
+extendedKeyUsage=serverAuth"
+leaf client ca client "extendedKeyUsage=clientAuth"
+leaf revoked ca revoked "extendedKeyUsage=clientAuth"
+leaf intruder other-ca intruder "extendedKeyUsage=clientAuth"
+
+# a CRL of `ca` revoking `revoked`
+cat > crl.cnf <<CNF
+[ca]
+default_ca = test
+[test]
+database = index.txt
+crlnumber = crlnumber
+default_md = sha256
+default_crl_days = $days
+CNF
+: > index.txt
+echo 01 > crlnumber
+openssl ca -config crl.cnf -keyfile ca.key -cert ca.crt -revoke revoked.crt
+openssl ca -config crl.cnf -keyfile ca.key -cert ca.crt -gencrl -out ca.crl
+rm -f crl.cnf index.txt* crlnumber* *.srl
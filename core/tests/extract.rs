@@ -0,0 +1,72 @@
+// `#[route]` registers through a ctor guarded by the `used_linker` cfg
+#![allow(unexpected_cfgs)]
+
+use std::net::SocketAddr;
+
+use argos::{error::ReturnError, extract::Path, request::HttpRequest, server::Server, Chain};
+use argos_macros::{filter, route, register};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+#[route(GET, path = "/extract/people/:name([a-z ]+)", formatter = "text")]
+pub async fn person(Path((name,)): Path<(String,)>) -> Result<String, ReturnError<String>> {
+    Ok(name)
+}
+
+#[route(GET, path = "/extract/files/*path", formatter = "text")]
+pub async fn file(Path((path,)): Path<(String,)>) -> Result<String, ReturnError<String>> {
+    Ok(path)
+}
+
+#[route(GET, path = "/extract/admin/secret", formatter = "text")]
+pub async fn secret() -> Result<String, ReturnError<String>> {
+    Ok("secret".to_string())
+}
+
+#[filter(path_pattern = "^/extract/admin", order = 1)]
+pub fn admin_only(req: HttpRequest) -> Chain {
+    if req.headers().contains_key("token") {
+        Chain::Continune(req)
+    } else {
+        Chain::Reject(ReturnError::new(401, "not authorized".to_string()))
+    }
+}
+
+async fn serve() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder_from_listener(listener).build().await.unwrap();
+    tokio::spawn(async move {
+        server.start_with_shutdown(std::future::pending()).await.unwrap();
+    });
+    addr
+}
+
+/// Sends a `GET` for `path` as is and returns the status and body.
+async fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[tokio::test]
+async fn path_parameters_are_percent_decoded() {
+    let addr = serve().await;
+    assert_eq!(get(addr, "/extract/people/john%20doe").await, (200, "john doe".to_string()));
+    assert_eq!(get(addr, "/extract/people/john").await, (200, "john".to_string()));
+    // the constraint is matched against the decoded segment
+    assert_eq!(get(addr, "/extract/people/john%2Fdoe").await.0, 404);
+    assert_eq!(get(addr, "/extract/files/a%20b/c%2Fd").await, (200, "a b/c/d".to_string()));
+}
+
+#[tokio::test]
+async fn filters_match_the_decoded_path() {
+    let addr = serve().await;
+    assert_eq!(get(addr, "/extract/admin/secret").await.0, 401);
+    assert_eq!(get(addr, "/extract/%61dmin/secret").await.0, 401);
+    assert_eq!(get(addr, "/%65xtract/admin%2Fsecret").await.0, 401);
+}
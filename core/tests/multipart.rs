@@ -0,0 +1,100 @@
+// `#[route]` registers through a ctor guarded by the `used_linker` cfg
+#![allow(unexpected_cfgs)]
+
+use std::net::SocketAddr;
+
+use argos::{error::ReturnError, multipart::MultipartLimits, request::HttpRequest, server::Server};
+use argos_macros::{route, register};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+const BOUNDARY: &str = "X-ARGOS-BOUNDARY";
+
+/// Answers `name=size` for each part, with a 16 byte limit per part and a
+/// 256 byte limit for the whole body.
+#[route(POST, path = "/multipart/upload", formatter = "text")]
+pub async fn upload(mut req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let mut multipart = req.multipart_with_limits(MultipartLimits::new().per_part(16).total(256))?;
+    let mut parts = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or("-").to_string();
+        parts.push(format!("{}={}", name, field.bytes().await?.len()));
+    }
+    Ok(parts.join(" "))
+}
+
+async fn serve() -> SocketAddr {
+    let server = Server::builder_from_listener(std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+        .build()
+        .await
+        .unwrap();
+    let addr = server.local_addrs()[0];
+    tokio::spawn(async move {
+        server.start_with_shutdown(std::future::pending()).await.unwrap();
+    });
+    addr
+}
+
+/// A multipart body with a part for each `(name, content)`.
+fn form(parts: &[(&str, &str)]) -> String {
+    let mut body = String::new();
+    for (name, content) in parts {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, content);
+    }
+    body + &format!("--{}--\r\n", BOUNDARY)
+}
+
+/// Posts `body` to `/multipart/upload` and returns the status code and body.
+async fn post(addr: SocketAddr, content_type: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "POST /multipart/upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body,
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head[9..12].parse().unwrap(), body.to_string())
+}
+
+fn multipart() -> String {
+    format!("multipart/form-data; boundary={}", BOUNDARY)
+}
+
+#[tokio::test]
+async fn parts_within_the_limits_are_read() {
+    let addr = serve().await;
+    let (status, body) = post(addr, &multipart(), &form(&[("title", "hello"), ("file", "0123456789abcdef")])).await;
+    assert_eq!((status, body.as_str()), (200, "title=5 file=16"));
+}
+
+#[tokio::test]
+async fn parts_over_the_limits_are_rejected_with_413() {
+    let addr = serve().await;
+    let (status, body) = post(addr, &multipart(), &form(&[("file", "0123456789abcdefg")])).await;
+    assert_eq!(status, 413, "{}", body);
+
+    // every part fits, all of them together do not
+    let parts = vec![("part", "0123456789"); 4];
+    let (status, body) = post(addr, &multipart(), &form(&parts)).await;
+    assert_eq!(status, 413, "{}", body);
+}
+
+#[tokio::test]
+async fn malformed_bodies_are_rejected_with_400() {
+    let addr = serve().await;
+    let truncated = format!("--{}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhel", BOUNDARY);
+    assert_eq!(post(addr, &multipart(), &truncated).await.0, 400);
+    assert_eq!(post(addr, &multipart(), "no boundary in here").await.0, 400);
+}
+
+#[tokio::test]
+async fn other_content_types_are_rejected_with_415() {
+    let addr = serve().await;
+    let content = form(&[("title", "hello")]);
+    assert_eq!(post(addr, "application/x-www-form-urlencoded", &content).await.0, 415);
+    // no boundary to split the parts at
+    assert_eq!(post(addr, "multipart/form-data", &content).await.0, 415);
+}
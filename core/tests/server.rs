@@ -0,0 +1,202 @@
+// `#[route]` registers through a ctor guarded by the `used_linker` cfg
+#![allow(unexpected_cfgs)]
+
+use std::{net::SocketAddr, time::Duration};
+
+use argos::{error::ReturnError, request::HttpRequest, server::{Server, ServerBuilder}};
+use argos_macros::{route, register};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::oneshot, task::JoinHandle};
+
+#[route(GET, path = "/server/slow", formatter = "text")]
+pub async fn slow(_req: HttpRequest) -> Result<String, ReturnError<String>> {
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    Ok("done".to_string())
+}
+
+#[route(GET, path = "/server/methods/items", formatter = "text")]
+pub async fn list_items(_req: HttpRequest) -> Result<String, ReturnError<String>> {
+    Ok("items".to_string())
+}
+
+#[route(POST, path = "/server/methods/:name", formatter = "text")]
+pub async fn create_item(_req: HttpRequest) -> Result<String, ReturnError<String>> {
+    Ok("created".to_string())
+}
+
+#[route(ANY, path = "/server/methods/any", formatter = "text")]
+pub async fn any_method(req: HttpRequest) -> Result<String, ReturnError<String>> {
+    Ok(req.method().to_string())
+}
+
+/// Starts the server built by `builder` on a free port, it stops once the
+/// returned sender is dropped.
+async fn serve(builder: &mut ServerBuilder) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let server = builder.build().await.unwrap();
+    let addr = server.local_addrs()[0];
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        let stopped = async move {
+            let _ = stop_rx.await;
+        };
+        server.start_with_shutdown(stopped).await.unwrap();
+    });
+    (addr, stop_tx, handle)
+}
+
+fn builder() -> ServerBuilder {
+    Server::builder_from_listener(std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+}
+
+/// Sends `method path` on a new connection and returns the lowercased
+/// response head and the body.
+async fn request(addr: SocketAddr, method: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", method, path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_ascii_lowercase(), body.to_string())
+}
+
+/// Waits for the server to close the connection, failing after `within`.
+async fn assert_closed(stream: &mut TcpStream, within: Duration) {
+    let mut buf = [0; 64];
+    let read = tokio::time::timeout(within, stream.read(&mut buf)).await
+        .expect("the connection is still open");
+    assert!(matches!(read, Ok(0) | Err(_)), "unexpected response: {:?}", read);
+}
+
+#[tokio::test]
+async fn silent_client_is_dropped_while_sniffing_the_preface() {
+    let (addr, _stop, _) = serve(builder().auto().header_read_timeout(Duration::from_millis(200))).await;
+
+    let mut silent = TcpStream::connect(addr).await.unwrap();
+    assert_closed(&mut silent, Duration::from_secs(2)).await;
+
+    let mut partial = TcpStream::connect(addr).await.unwrap();
+    partial.write_all(b"PRI").await.unwrap();
+    assert_closed(&mut partial, Duration::from_secs(2)).await;
+}
+
+#[tokio::test]
+async fn shutdown_does_not_wait_for_a_client_sniffing_the_preface() {
+    let (addr, stop, handle) = serve(builder().auto().shutdown_timeout(Duration::from_secs(30))).await;
+
+    let mut partial = TcpStream::connect(addr).await.unwrap();
+    partial.write_all(b"PRI").await.unwrap();
+    // let the server accept the connection before stopping it
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(stop);
+    tokio::time::timeout(Duration::from_secs(2), handle).await
+        .expect("the shutdown waited for the connection")
+        .unwrap();
+    assert_closed(&mut partial, Duration::from_secs(2)).await;
+}
+
+#[tokio::test]
+async fn keep_alive_timeout_spares_requests_in_flight() {
+    let (addr, _stop, _) = serve(builder().keep_alive_timeout(Duration::from_millis(200))).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /server/slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    while !response.ends_with(b"done") {
+        let read = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf)).await.unwrap().unwrap();
+        assert!(read > 0, "closed during the request: {:?}", String::from_utf8_lossy(&response));
+        response.extend_from_slice(&buf[..read]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(!String::from_utf8_lossy(&response).to_ascii_lowercase().contains("connection: close"));
+
+    // the timer restarts once the response is sent, the connection is kept alive
+    stream.write_all(b"GET /server/missing HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let read = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf)).await.unwrap().unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 404 Not Found\r\n"), "{:?}", String::from_utf8_lossy(&buf[..read]));
+
+    // and closed after idling between requests
+    assert_closed(&mut stream, Duration::from_secs(2)).await;
+}
+
+#[tokio::test]
+async fn connections_are_aborted_after_the_shutdown_timeout() {
+    let (addr, stop, handle) = serve(builder().shutdown_timeout(Duration::from_millis(100))).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /server/slow HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(stop);
+    tokio::time::timeout(Duration::from_millis(400), handle).await
+        .expect("the shutdown timeout was not applied")
+        .unwrap();
+    // the slow handler would answer after 600ms, had it not been aborted
+    assert_closed(&mut stream, Duration::from_secs(2)).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_bind_only_replaces_stale_sockets() {
+    let dir = std::env::temp_dir().join(format!("argos-unix-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // left behind by a server that is gone
+    let stale = dir.join("stale.sock");
+    drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+    assert!(Server::builder_from_unix(&stale).build().await.is_ok());
+
+    // still served, it must not be taken over
+    let live = dir.join("live.sock");
+    let listener = std::os::unix::net::UnixListener::bind(&live).unwrap();
+    assert!(Server::builder_from_unix(&live).build().await.is_err());
+    assert!(std::os::unix::net::UnixStream::connect(&live).is_ok());
+    drop(listener);
+
+    let file = dir.join("file.sock");
+    std::fs::write(&file, "data").unwrap();
+    assert!(Server::builder_from_unix(&file).build().await.is_err());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "data");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unsupported_methods_get_405_with_the_merged_allow_header() {
+    let (addr, _stop, _) = serve(&mut builder()).await;
+    // `GET /server/methods/items` and `POST /server/methods/:name` both match
+    let (head, body) = request(addr, "PATCH", "/server/methods/items").await;
+    assert!(head.starts_with("http/1.1 405"), "{}", head);
+    assert!(head.contains("\r\nallow: get, head, options, post\r\n"), "{}", head);
+    assert!(body.is_empty());
+
+    let (head, _) = request(addr, "DELETE", "/server/methods/other").await;
+    assert!(head.contains("\r\nallow: options, post\r\n"), "{}", head);
+    assert!(request(addr, "GET", "/server/missing").await.0.starts_with("http/1.1 404"));
+}
+
+#[tokio::test]
+async fn options_is_answered_with_204_and_the_allowed_methods() {
+    let (addr, _stop, _) = serve(&mut builder()).await;
+    let (head, body) = request(addr, "OPTIONS", "/server/methods/items").await;
+    assert!(head.starts_with("http/1.1 204"), "{}", head);
+    assert!(head.contains("\r\nallow: get, head, options, post\r\n"), "{}", head);
+    assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn head_is_answered_by_the_get_route_without_a_body() {
+    let (addr, _stop, _) = serve(&mut builder()).await;
+    let (head, body) = request(addr, "HEAD", "/server/methods/items").await;
+    assert!(head.starts_with("http/1.1 200"), "{}", head);
+    assert!(head.contains("\r\ncontent-length: 5\r\n"), "{}", head);
+    assert!(body.is_empty(), "{:?}", body);
+}
+
+#[tokio::test]
+async fn any_routes_answer_every_method() {
+    let (addr, _stop, _) = serve(&mut builder()).await;
+    for method in ["GET", "POST", "PATCH", "DELETE", "OPTIONS"] {
+        let (head, body) = request(addr, method, "/server/methods/any").await;
+        assert!(head.starts_with("http/1.1 200"), "{} {}", method, head);
+        assert_eq!(body, method);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use argos::{server::{Server, ServerBuilder}, static_files::StaticDir};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+/// A fresh directory below the temp dir, with `outside` next to it.
+fn site(name: &str) -> PathBuf {
+    let base = std::env::temp_dir().join(format!("argos-static-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(base.join("site/evil.com")).unwrap();
+    std::fs::create_dir_all(base.join("site/docs")).unwrap();
+    std::fs::write(base.join("site/index.html"), "home").unwrap();
+    std::fs::write(base.join("site/docs/index.html"), "docs").unwrap();
+    std::fs::write(base.join("outside.txt"), "secret").unwrap();
+    base
+}
+
+async fn serve(builder: &mut ServerBuilder) -> SocketAddr {
+    let server = builder.build().await.unwrap();
+    let addr = server.local_addrs()[0];
+    tokio::spawn(async move {
+        server.start_with_shutdown(std::future::pending()).await.unwrap();
+    });
+    addr
+}
+
+fn builder() -> ServerBuilder {
+    Server::builder_from_listener(std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+}
+
+/// Sends a `GET` for `path` as is and returns the response head and body.
+async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_ascii_lowercase(), body.to_string())
+}
+
+#[tokio::test]
+async fn directory_redirects_stay_on_the_server() {
+    let base = site("redirect");
+    let addr = serve(builder().static_dir("/", base.join("site").to_str().unwrap())).await;
+
+    let (head, _) = get(addr, "//evil.com").await;
+    assert!(head.starts_with("http/1.1 301"), "{}", head);
+    assert!(head.contains("\r\nlocation: /evil.com/\r\n"), "{}", head);
+
+    let (head, _) = get(addr, "/docs?page=2").await;
+    assert!(head.contains("\r\nlocation: /docs/?page=2\r\n"), "{}", head);
+    assert_eq!(get(addr, "/docs/").await.1, "docs");
+
+    let addr = serve(builder().static_dir("/assets/", base.join("site").to_str().unwrap())).await;
+    let (head, _) = get(addr, "/assets//./docs").await;
+    assert!(head.contains("\r\nlocation: /assets/docs/\r\n"), "{}", head);
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_out_of_the_directory_are_not_followed() {
+    let base = site("symlink");
+    std::os::unix::fs::symlink(base.join("outside.txt"), base.join("site/leak.txt")).unwrap();
+    std::os::unix::fs::symlink(base.join("site/index.html"), base.join("site/home.html")).unwrap();
+
+    let addr = serve(builder().static_dir("/", base.join("site").to_str().unwrap())).await;
+    assert!(get(addr, "/leak.txt").await.0.starts_with("http/1.1 404"));
+    assert_eq!(get(addr, "/home.html").await.1, "home");
+
+    let dir = StaticDir::new(base.join("site")).follow_symlinks(true);
+    let addr = serve(builder().static_files("/", dir)).await;
+    assert_eq!(get(addr, "/leak.txt").await.1, "secret");
+    std::fs::remove_dir_all(&base).unwrap();
+}
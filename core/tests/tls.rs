@@ -0,0 +1,160 @@
+// `#[route]` registers through a ctor guarded by the `used_linker` cfg
+#![allow(unexpected_cfgs)]
+#![cfg(any(feature = "boring", feature = "rustls"))]
+
+use std::{fs::File, io::BufReader, net::SocketAddr, sync::Arc};
+
+use argos::{error::ReturnError, request::HttpRequest, server::{ClientAuth, Server, ServerBuilder, SslFiletype}};
+use argos_macros::{route, register};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+use tokio_rustls::{rustls, TlsConnector};
+
+/// Answers with the server name, protocol and verified client of the session.
+#[route(GET, path = "/tls/session", formatter = "text")]
+pub async fn session(req: HttpRequest) -> Result<String, ReturnError<String>> {
+    let tls = req.tls_info().ok_or_else(|| ReturnError::new(500, "not TLS".to_string()))?;
+    Ok(format!(
+        "{} {} {}",
+        tls.server_name().unwrap_or("-"),
+        tls.alpn_protocol().unwrap_or("-"),
+        tls.peer_subject().unwrap_or("-"),
+    ))
+}
+
+fn cert(name: &str) -> String {
+    format!("{}/tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// A server for `localhost`, and `api.example.com` through SNI.
+fn builder() -> ServerBuilder {
+    let mut builder = Server::builder_from_listener(std::net::TcpListener::bind("127.0.0.1:0").unwrap());
+    builder
+        .ssl(&cert("server.key"), &cert("server.crt"), SslFiletype::PEM).unwrap()
+        .ssl_sni("api.example.com", &cert("api.key"), &cert("api.crt"), SslFiletype::PEM).unwrap();
+    builder
+}
+
+async fn serve(builder: &mut ServerBuilder) -> SocketAddr {
+    let server = builder.build().await.unwrap();
+    let addr = server.local_addrs()[0];
+    tokio::spawn(async move {
+        server.start_with_shutdown(std::future::pending()).await.unwrap();
+    });
+    addr
+}
+
+/// Requests `/tls/session` as `server_name`, with the certificate of `client`
+/// if given. `None` when the server refused the client.
+async fn session_of(addr: SocketAddr, server_name: &str, client: Option<&str>) -> Option<String> {
+    let mut roots = rustls::RootCertStore::empty();
+    for ca in rustls_pemfile::certs(&mut BufReader::new(File::open(cert("ca.crt")).unwrap())).unwrap() {
+        roots.add(&rustls::Certificate(ca)).unwrap();
+    }
+    let config = rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(roots);
+    let mut config = match client {
+        Some(client) => {
+            let chain = rustls_pemfile::certs(&mut BufReader::new(File::open(cert(&format!("{}.crt", client))).unwrap())).unwrap();
+            let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(cert(&format!("{}.key", client))).unwrap())).unwrap();
+            config.with_client_auth_cert(
+                chain.into_iter().map(rustls::Certificate).collect(),
+                rustls::PrivateKey(key.into_iter().next().unwrap()),
+            ).unwrap()
+        }
+        None => config.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let server_name = rustls::ServerName::try_from(server_name).unwrap();
+    // with TLS 1.3 a refused client certificate only shows on the first read
+    let mut stream = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await.ok()?;
+    stream.write_all(b"GET /tls/session HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.ok()?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.ok()?;
+    let response = String::from_utf8(response).ok()?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    Some(response.split_once("\r\n\r\n")?.1.to_string())
+}
+
+#[tokio::test]
+async fn required_client_auth_accepts_certificates_of_the_ca() {
+    let addr = serve(builder().client_auth(ClientAuth::required(&cert("ca.crt")))).await;
+    assert_eq!(session_of(addr, "localhost", Some("client")).await.as_deref(), Some("localhost http/1.1 CN=client"));
+    assert_eq!(session_of(addr, "localhost", None).await, None);
+    assert_eq!(session_of(addr, "localhost", Some("intruder")).await, None);
+}
+
+#[tokio::test]
+async fn optional_client_auth_accepts_clients_without_a_certificate() {
+    let addr = serve(builder().client_auth(ClientAuth::optional(&cert("ca.crt")))).await;
+    assert_eq!(session_of(addr, "localhost", Some("client")).await.as_deref(), Some("localhost http/1.1 CN=client"));
+    assert_eq!(session_of(addr, "localhost", None).await.as_deref(), Some("localhost http/1.1 -"));
+    // a certificate that is sent must still verify
+    assert_eq!(session_of(addr, "localhost", Some("intruder")).await, None);
+}
+
+#[tokio::test]
+async fn revoked_client_certificates_are_refused() {
+    let addr = serve(builder().client_auth(ClientAuth::required(&cert("ca.crt")).crl_file(&cert("ca.crl")))).await;
+    assert_eq!(session_of(addr, "localhost", Some("client")).await.as_deref(), Some("localhost http/1.1 CN=client"));
+    assert_eq!(session_of(addr, "localhost", Some("revoked")).await, None);
+}
+
+#[tokio::test]
+async fn bad_ca_and_crl_files_fail_the_build() {
+    assert!(builder().client_auth(ClientAuth::required(&cert("missing.crt"))).build().await.is_err());
+    // a key is no CA
+    assert!(builder().client_auth(ClientAuth::required(&cert("ca.key"))).build().await.is_err());
+
+    // a certificate next to the CRL would be trusted as a CA
+    let crl_with_cert = std::env::temp_dir().join(format!("argos-crl-with-cert-{}.pem", std::process::id()));
+    let contents = std::fs::read_to_string(cert("ca.crl")).unwrap() + &std::fs::read_to_string(cert("other-ca.crt")).unwrap();
+    std::fs::write(&crl_with_cert, contents).unwrap();
+    let client_auth = ClientAuth::required(&cert("ca.crt")).crl_file(crl_with_cert.to_str().unwrap());
+    let built = builder().client_auth(client_auth).build().await;
+    std::fs::remove_file(&crl_with_cert).unwrap();
+    assert!(built.is_err());
+}
+
+#[tokio::test]
+async fn sni_hosts_verify_clients_and_negotiate_alpn() {
+    let addr = serve(builder().client_auth(ClientAuth::required(&cert("ca.crt")))).await;
+    assert_eq!(
+        session_of(addr, "api.example.com", Some("client")).await.as_deref(),
+        Some("api.example.com http/1.1 CN=client"),
+    );
+    assert_eq!(session_of(addr, "api.example.com", None).await, None);
+    assert_eq!(session_of(addr, "api.example.com", Some("intruder")).await, None);
+
+    let addr = serve(builder().client_auth(ClientAuth::optional(&cert("ca.crt")))).await;
+    assert_eq!(
+        session_of(addr, "api.example.com", Some("client")).await.as_deref(),
+        Some("api.example.com http/1.1 CN=client"),
+    );
+    assert_eq!(session_of(addr, "api.example.com", None).await.as_deref(), Some("api.example.com http/1.1 -"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn certificates_are_reloaded_on_sighup_when_enabled() {
+    let dir = std::env::temp_dir().join(format!("argos-tls-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (key, crt) = (dir.join("server.key"), dir.join("server.crt"));
+    std::fs::copy(cert("server.key"), &key).unwrap();
+    std::fs::copy(cert("server.crt"), &crt).unwrap();
+
+    let mut builder = Server::builder_from_listener(std::net::TcpListener::bind("127.0.0.1:0").unwrap());
+    builder.ssl(key.to_str().unwrap(), crt.to_str().unwrap(), SslFiletype::PEM).unwrap().tls_reload_on_sighup(true);
+    let addr = serve(&mut builder).await;
+    assert_eq!(session_of(addr, "localhost", None).await.as_deref(), Some("localhost http/1.1 -"));
+
+    // the certificate for another name is served after the signal
+    std::fs::copy(cert("api.key"), &key).unwrap();
+    std::fs::copy(cert("api.crt"), &crt).unwrap();
+    let killed = std::process::Command::new("kill").arg("-HUP").arg(std::process::id().to_string()).status().unwrap();
+    assert!(killed.success());
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(session_of(addr, "localhost", None).await, None);
+    assert_eq!(session_of(addr, "api.example.com", None).await.as_deref(), Some("api.example.com http/1.1 -"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
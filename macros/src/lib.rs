@@ -1,7 +1,7 @@
 mod parse;
 use proc_macro::TokenStream;
 use quote::{quote, format_ident};
-use syn::{parse_macro_input, ItemFn, ReturnType};
+use syn::{parse_macro_input, FnArg, ItemFn, ReturnType};
 
 #[doc(hidden)]
 macro_rules! ctor_attributes {
@@ -169,7 +169,7 @@ pub fn route(
 
     // parse args
     let attribute = parse_macro_input!(args as parse::RouteAttribute);
-    let http_method = attribute.http_method.to_string();
+    let http_methods: Vec<String> = attribute.http_methods.iter().map(|m| m.to_string()).collect();
     let path = attribute.path;
     let formatter = attribute.formatter;
 
@@ -181,23 +181,27 @@ pub fn route(
     // println!("{:?}", return_type);
     // rebuild new function
     let fn_name = &function.sig.ident;
-    let fn_args = &function.sig.inputs;
-    let fn_block = &function.block;
+    let RouteHandler { inner_fn, extract, call } = match route_handler(&function) {
+        Ok(handler) => handler,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     let f = formatter.value();
     let handler = match f.as_str() {
         "json" => {
             quote! {
-                pub fn #fn_name(#fn_args) -> 
-                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::util::Full<argos::util::Bytes>>, argos::error::Error>> + Send>> {
+                pub fn #fn_name(__argos_req: argos::request::HttpRequest) -> 
+                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::response::Body>, argos::error::Error>> + Send>> {
+                    #inner_fn
                     Box::pin(async move {
-                        let result: #return_type = #fn_block;
+                        #extract
+                        let result: #return_type = #call;
                         let response = match result {
                             Ok(data) => {
                                 let response_body = serde_json::to_string(&data).unwrap();
                                 argos::response::Response::builder()
                                     .header("Content-Type", "application/json")
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                             Err(err) => {
@@ -209,7 +213,7 @@ pub fn route(
                                 builder
                                     .header("Content-Type", "application/json")
                                     .status(err.response_code)
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                         };
@@ -220,16 +224,18 @@ pub fn route(
         },
         "text" => {
             quote! {
-                pub fn #fn_name(#fn_args) -> 
-                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::util::Full<argos::util::Bytes>>, argos::error::Error>> + Send>> {
+                pub fn #fn_name(__argos_req: argos::request::HttpRequest) -> 
+                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::response::Body>, argos::error::Error>> + Send>> {
+                    #inner_fn
                     Box::pin(async move {
-                        let result: #return_type = #fn_block;
+                        #extract
+                        let result: #return_type = #call;
                         let response = match result {
                             Ok(data) => {
                                 let response_body = format!("{}", data);
                                 argos::response::Response::builder()
                                     .header("Content-Type", "text/plain")
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                             Err(err) => {
@@ -241,7 +247,36 @@ pub fn route(
                                 builder
                                     .header("Content-Type", "text/plain")
                                     .status(err.response_code)
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
+                                    .unwrap()
+                            },
+                        };
+                        return Ok(response);
+                    })
+                }
+            }
+        },
+        "response" => {
+            // the function builds the whole response, e.g. with a streamed body
+            quote! {
+                pub fn #fn_name(__argos_req: argos::request::HttpRequest) -> 
+                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::response::Body>, argos::error::Error>> + Send>> {
+                    #inner_fn
+                    Box::pin(async move {
+                        #extract
+                        let result: #return_type = #call;
+                        let response = match result {
+                            Ok(response) => response,
+                            Err(err) => {
+                                let response_body = format!("{}", err.response_body);
+                                let mut builder = argos::response::Response::builder();
+                                for (k, v) in err.headers.iter() {
+                                    builder.headers_mut().unwrap().insert(k, v.clone());
+                                }
+                                builder
+                                    .header("Content-Type", "text/plain")
+                                    .status(err.response_code)
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                         };
@@ -252,16 +287,18 @@ pub fn route(
         },
         _ => {
             quote! {
-                pub fn #fn_name(#fn_args) -> 
-                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::util::Full<argos::util::Bytes>>, argos::error::Error>> + Send>> {
+                pub fn #fn_name(__argos_req: argos::request::HttpRequest) -> 
+                    std::pin::Pin<Box<dyn std::future::Future<Output = Result<argos::response::Response<argos::response::Body>, argos::error::Error>> + Send>> {
+                    #inner_fn
                     Box::pin(async move {
-                        let result: #return_type = #fn_block;
+                        #extract
+                        let result: #return_type = #call;
                         let response = match result {
                             Ok(data) => {
                                 let response_body = format!("{}", data);
                                 argos::response::Response::builder()
                                     .header("Content-Type", "text/html")
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                             Err(err) => {
@@ -272,7 +309,7 @@ pub fn route(
                                 }
                                 builder.header("Content-Type", "text/html")
                                     .status(err.response_code)
-                                    .body(argos::util::Full::new(argos::util::Bytes::from(response_body)))
+                                    .body(argos::response::Body::from(response_body))
                                     .unwrap()
                             },
                         };
@@ -288,13 +325,16 @@ pub fn route(
     let register_fn = quote! {
         #[register]
         fn #register_fn_name() {
-            argos::ROUTE_TABLE.write().unwrap().push(
-                argos::RouteInfo::new(
-                    #http_method.to_string(),
-                    argos::Path::new(#path),
-                    Box::new(#fn_name)
-                )
-            );
+            let mut table = argos::ROUTE_TABLE.write().unwrap();
+            #(
+                table.push(
+                    std::sync::Arc::new(argos::RouteInfo::new(
+                        #http_methods.to_string(),
+                        argos::Path::new(#path),
+                        Box::new(#fn_name)
+                    ))
+                );
+            )*
         }
     };
     
@@ -318,7 +358,6 @@ pub fn filter(
     let predicate_value = attribute.predicate_value;
     let order = attribute.order;
 
-    // get the return type of the function
     // get the return type of the function
     let return_type = match &function.sig.output {
         ReturnType::Default => quote!(()),
@@ -327,14 +366,14 @@ pub fn filter(
   
     // rebuild new function
     let fn_name = &function.sig.ident;
-    let fn_args = &function.sig.inputs;
-    let fn_block = &function.block;
+    let InnerHandler { inner_fn, fn_args, call } = inner_handler(&function);
 
     let handler = quote! {
         pub fn #fn_name(#fn_args) -> std::pin::Pin<Box<dyn std::future::Future<Output = argos::Chain> + Send>> {
+            #inner_fn
             Box::pin(
                 async move {
-                    let result: #return_type = #fn_block;
+                    let result: #return_type = #call;
                     result
                 }
             )
@@ -363,6 +402,191 @@ pub fn filter(
     })
 }
 
+struct InnerHandler {
+    inner_fn: proc_macro2::TokenStream,
+    fn_args: proc_macro2::TokenStream,
+    call: proc_macro2::TokenStream,
+}
+
+/// Keeps the user's function as a nested item so that `return`, `?` and
+/// `.await` behave as written, whether it is declared `async` or not. The
+/// generated wrapper takes plain positional arguments and forwards them.
+fn inner_handler(function: &ItemFn) -> InnerHandler {
+    let inner_ident = format_ident!("__argos_{}", function.sig.ident);
+    let mut sig = function.sig.clone();
+    sig.ident = inner_ident.clone();
+    let attrs = &function.attrs;
+    let block = &function.block;
+    let inner_fn = quote! {
+        #(#attrs)*
+        #sig #block
+    };
+
+    let mut args = Vec::new();
+    let mut names = Vec::new();
+    for (i, input) in function.sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Typed(pat_type) => {
+                let name = format_ident!("__argos_arg{}", i);
+                let ty = &pat_type.ty;
+                args.push(quote!(#name: #ty));
+                names.push(name);
+            }
+            FnArg::Receiver(receiver) => {
+                let err = syn::Error::new_spanned(receiver, "handlers can not take `self`").to_compile_error();
+                return InnerHandler { inner_fn: err, fn_args: quote!(), call: quote!(unreachable!()) };
+            }
+        }
+    }
+
+    let call = if function.sig.asyncness.is_some() {
+        quote!(#inner_ident(#(#names),*).await)
+    } else {
+        quote!(#inner_ident(#(#names),*))
+    };
+
+    InnerHandler {
+        inner_fn,
+        fn_args: quote!(#(#args),*),
+        call,
+    }
+}
+
+struct RouteHandler {
+    inner_fn: proc_macro2::TokenStream,
+    extract: proc_macro2::TokenStream,
+    call: proc_macro2::TokenStream,
+}
+
+/// Like [`inner_handler`], but the generated wrapper always takes the
+/// `HttpRequest` and builds each parameter of the user's function from it:
+/// `HttpRequest` itself is passed through, `Header<"name">` is rewritten to
+/// `argos::extract::Header` and any other type goes through
+/// `argos::extract::FromRequest`. A failed extraction returns its error as
+/// the response without calling the function.
+fn route_handler(function: &ItemFn) -> syn::Result<RouteHandler> {
+    let inner_ident = format_ident!("__argos_{}", function.sig.ident);
+    let mut sig = function.sig.clone();
+    sig.ident = inner_ident.clone();
+
+    let mut extract = Vec::new();
+    let mut names = Vec::new();
+    let mut request_arg = None;
+    for (i, input) in sig.inputs.iter_mut().enumerate() {
+        let pat_type = match input {
+            FnArg::Typed(pat_type) => pat_type,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, "handlers can not take `self`"));
+            }
+        };
+        let name = format_ident!("__argos_arg{}", i);
+        let ty = &pat_type.ty;
+        if last_segment(ty).map_or(false, |segment| segment.ident == "HttpRequest") {
+            if request_arg.is_some() {
+                return Err(syn::Error::new_spanned(ty, "a handler can only take one `HttpRequest`"));
+            }
+            request_arg = Some(name.clone());
+        } else if let Some(header) = header_name(ty) {
+            extract.push(quote! {
+                let #name = match argos::extract::Header::extract(&__argos_req, #header) {
+                    Ok(value) => value,
+                    Err(err) => return Ok(err.into_response()),
+                };
+            });
+            strip_header_name(&mut pat_type.ty);
+        } else if let Some(header) = option_inner(ty).as_ref().and_then(header_name) {
+            extract.push(quote! {
+                let #name = match argos::extract::Header::extract_optional(&__argos_req, #header) {
+                    Ok(value) => value,
+                    Err(err) => return Ok(err.into_response()),
+                };
+            });
+            strip_header_name(&mut pat_type.ty);
+        } else {
+            extract.push(quote! {
+                let #name = match <#ty as argos::extract::FromRequest>::from_request(&mut __argos_req).await {
+                    Ok(value) => value,
+                    Err(err) => return Ok(err.into_response()),
+                };
+            });
+        }
+        names.push(name);
+    }
+    // the request is moved into its parameter once everything else is extracted
+    if let Some(name) = request_arg {
+        extract.push(quote!(let #name = __argos_req;));
+    }
+
+    let attrs = &function.attrs;
+    let block = &function.block;
+    let inner_fn = quote! {
+        #(#attrs)*
+        #sig #block
+    };
+    let call = if function.sig.asyncness.is_some() {
+        quote!(#inner_ident(#(#names),*).await)
+    } else {
+        quote!(#inner_ident(#(#names),*))
+    };
+
+    Ok(RouteHandler {
+        inner_fn,
+        extract: quote! {
+            #[allow(unused_mut)]
+            let mut __argos_req = __argos_req;
+            #(#extract)*
+        },
+        call,
+    })
+}
+
+fn last_segment(ty: &syn::Type) -> Option<&syn::PathSegment> {
+    match ty {
+        syn::Type::Path(type_path) => type_path.path.segments.last(),
+        _ => None,
+    }
+}
+
+fn single_generic(ty: &syn::Type, ident: &str) -> Option<syn::GenericArgument> {
+    let segment = last_segment(ty).filter(|segment| segment.ident == ident)?;
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => args.args.first().cloned(),
+        _ => None,
+    }
+}
+
+/// `Header<"x-token">` gives `"x-token"`.
+fn header_name(ty: &syn::Type) -> Option<syn::LitStr> {
+    match single_generic(ty, "Header")? {
+        syn::GenericArgument::Const(syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(name), .. })) => Some(name),
+        _ => None,
+    }
+}
+
+/// Turns `Header<"x-token">` into `Header`, also inside an `Option`, keeping
+/// the path as written so the user's import stays in use.
+fn strip_header_name(ty: &mut syn::Type) {
+    if let syn::Type::Path(type_path) = ty {
+        if let Some(segment) = type_path.path.segments.last_mut() {
+            if segment.ident == "Header" {
+                segment.arguments = syn::PathArguments::None;
+            } else if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                if let Some(syn::GenericArgument::Type(inner)) = args.args.first_mut() {
+                    strip_header_name(inner);
+                }
+            }
+        }
+    }
+}
+
+/// `Option<T>` gives `T`.
+fn option_inner(ty: &syn::Type) -> Option<syn::Type> {
+    match single_generic(ty, "Option")? {
+        syn::GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
 fn validate_item(typ: &str, item: &syn::ItemFn) {
     let syn::ItemFn { vis, sig, .. } = item;
 
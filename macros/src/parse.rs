@@ -1,21 +1,46 @@
 
 use syn::parse::{Parse, ParseStream};
-use syn::{ Ident, Token, token, LitStr, Error, LitInt};
+use syn::{ bracketed, Ident, Token, token, LitStr, Error, LitInt};
 
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct RouteAttribute {
-    pub(crate) http_method: Ident,
+    pub(crate) http_methods: Vec<Ident>,
     pub(crate) path: LitStr,
     pub(crate) formatter: LitStr,
 }
 
 impl Parse for RouteAttribute {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let methods = vec!["GET", "POST", "OPTIONS", "HEAD", "DELETE", "PUT", "TRACE", "CONNECT", "PATCH"];
-        let http_method: Ident = input.parse()?;
-        
-        if methods.iter().find(|m| m.to_string() == http_method.to_string()).is_none() {
-            return Err(Error::new(http_method.span(), "expected http method"));
+        let methods = vec!["GET", "POST", "OPTIONS", "HEAD", "DELETE", "PUT", "TRACE", "CONNECT", "PATCH", "ANY"];
+        // either `GET | POST` or `method = [GET, POST]`
+        let http_methods: Vec<Ident> = if input.peek(Ident) && input.peek2(token::Eq) {
+            let method_ident: Ident = input.parse()?;
+            if method_ident != "method" {
+                return Err(Error::new(method_ident.span(), "expected http method"));
+            }
+            let _eq = input.parse::<Token![=]>()?;
+            let content;
+            let _bracket = bracketed!(content in input);
+            content.parse_terminated(Ident::parse, Token![,])?.into_iter().collect()
+        } else {
+            let mut http_methods = vec![input.parse::<Ident>()?];
+            while input.peek(Token![|]) {
+                let _or = input.parse::<Token![|]>()?;
+                http_methods.push(input.parse()?);
+            }
+            http_methods
+        };
+
+        if http_methods.is_empty() {
+            return Err(input.error("expected http method"));
+        }
+        for (i, http_method) in http_methods.iter().enumerate() {
+            if methods.iter().find(|m| m.to_string() == http_method.to_string()).is_none() {
+                return Err(Error::new(http_method.span(), "expected http method"));
+            }
+            if http_methods[..i].contains(http_method) {
+                return Err(Error::new(http_method.span(), "duplicate http method"));
+            }
         }
         let _comma = input.parse::<Token![,]>()?;
         let path_ident: Ident = input.parse()?;
@@ -31,12 +56,12 @@ impl Parse for RouteAttribute {
         }
         let _eq = input.parse::<Token![=]>()?;
         let formatter = input.parse::<LitStr>()?;
-        let formatters = vec!["json", "text", "html"];
+        let formatters = vec!["json", "text", "html", "response"];
         if formatters.iter().find(|f| f.to_string() == formatter.value()).is_none() {
-            return Err(Error::new(formatter.span(), "formatter only support json, text, html"));
+            return Err(Error::new(formatter.span(), "formatter only support json, text, html, response"));
         }
         Ok(RouteAttribute {
-            http_method,
+            http_methods,
             path,
             formatter,
         })